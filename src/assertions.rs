@@ -0,0 +1,422 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    IoSequence, Report, ReportItem, bench_settings::Preallocate, compare::row_name,
+    cpu_usage::CpuUsage, latency::LatencySummary, report::read_report,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CheckCommand {
+    #[clap(long, value_parser, default_value = "target/report.json")]
+    report_file: PathBuf,
+    /// JSON list of performance budgets
+    #[clap(long, value_parser)]
+    assertions_file: PathBuf,
+    /// Write the assertion results as JUnit XML
+    #[clap(long, value_parser)]
+    junit_file: Option<PathBuf>,
+}
+
+// A performance budget for every row matching the filters; filters that are left out match any
+// row. A row has to meet each of the limits that are given.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Assertion {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    // the method's type, e.g. "direct_uring"
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub block_size: Option<u32>,
+    #[serde(default)]
+    pub concurrency: Option<u32>,
+    #[serde(default)]
+    pub sequence: Option<IoSequence>,
+    #[serde(default)]
+    pub preallocate: Option<Preallocate>,
+    pub op: AssertedOp,
+    #[serde(default)]
+    pub min_mbps: Option<f64>,
+    #[serde(default)]
+    pub max_p99_us: Option<f64>,
+    #[serde(default)]
+    pub max_cpu_secs_per_gib: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AssertedOp {
+    Write,
+    Overwrite,
+    Read,
+}
+
+// One JUnit test case: an assertion's limit checked against one row.
+struct AssertionResult {
+    assertion: String,
+    case: String,
+    failure: Option<String>,
+}
+
+pub fn read_assertions(assertions_file: &Path) -> Vec<Assertion> {
+    let json = std::fs::read(assertions_file).unwrap();
+    serde_json::from_slice(&json).unwrap()
+}
+
+pub fn run_check(command: &CheckCommand) {
+    let report = read_report(&command.report_file);
+    let assertions = read_assertions(&command.assertions_file);
+    if !check_assertions(&report, &assertions, command.junit_file.as_deref()) {
+        std::process::exit(1);
+    }
+}
+
+// Prints every result and writes them to `junit_file` if given; true if all assertions passed.
+// An assertion that matches no row fails, so a typo in a filter can't pass CI unnoticed.
+pub fn check_assertions(
+    report: &Report,
+    assertions: &[Assertion],
+    junit_file: Option<&Path>,
+) -> bool {
+    let mut results = Vec::new();
+    for assertion in assertions {
+        let name = assertion
+            .name
+            .clone()
+            .unwrap_or_else(|| assertion.describe());
+        let rows = report
+            .items
+            .iter()
+            .filter(|item| assertion.matches(item))
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            results.push(AssertionResult {
+                assertion: name.clone(),
+                case: "matching rows".to_string(),
+                failure: Some("no row of the report matches".to_string()),
+            });
+        }
+        for item in rows {
+            assertion.check(item, &name, &mut results);
+        }
+    }
+
+    let failures = results
+        .iter()
+        .filter(|result| result.failure.is_some())
+        .count();
+    for result in &results {
+        match &result.failure {
+            Some(failure) => println!(
+                "FAIL {assertion}: {case}: {failure}",
+                assertion = result.assertion,
+                case = result.case
+            ),
+            None => println!(
+                "pass {assertion}: {case}",
+                assertion = result.assertion,
+                case = result.case
+            ),
+        }
+    }
+    println!(
+        "{passed} of {count} assertions passed",
+        passed = results.len() - failures,
+        count = results.len()
+    );
+    if let Some(junit_file) = junit_file {
+        std::fs::write(junit_file, junit_xml(&results, failures)).unwrap();
+    }
+    failures == 0
+}
+
+impl Assertion {
+    fn matches(&self, item: &ReportItem) -> bool {
+        self.target
+            .as_ref()
+            .is_none_or(|target| item.target.as_ref() == Some(target))
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|method| item.method.name() == method)
+            && self
+                .block_size
+                .is_none_or(|block_size| item.method.block_size() == block_size)
+            && self
+                .concurrency
+                .is_none_or(|concurrency| item.method.concurrency() == concurrency)
+            && self
+                .sequence
+                .is_none_or(|sequence| item.sequence == sequence)
+            && self
+                .preallocate
+                .is_none_or(|preallocate| item.preallocate == Some(preallocate))
+    }
+
+    // e.g. "direct_uring 64 KiB random read"
+    fn describe(&self) -> String {
+        let mut description = String::new();
+        if let Some(target) = &self.target {
+            write!(description, "{target} ").unwrap();
+        }
+        if let Some(method) = &self.method {
+            write!(description, "{method} ").unwrap();
+        }
+        if let Some(block_size) = self.block_size {
+            write!(description, "{} KiB ", block_size / 1024).unwrap();
+        }
+        if let Some(concurrency) = self.concurrency {
+            write!(description, "x{concurrency} ").unwrap();
+        }
+        if let Some(sequence) = self.sequence {
+            write!(description, "{} ", format!("{sequence:?}").to_lowercase()).unwrap();
+        }
+        if let Some(preallocate) = self.preallocate {
+            write!(description, "{preallocate:?} ").unwrap();
+        }
+        write!(description, "{}", format!("{:?}", self.op).to_lowercase()).unwrap();
+        description
+    }
+
+    fn check(&self, item: &ReportItem, name: &str, results: &mut Vec<AssertionResult>) {
+        let row = row_name(item);
+        let (mbps, latency, cpu) = match self.op {
+            AssertedOp::Write => (
+                Some(item.write_tput_mbps),
+                item.write_latency.as_ref(),
+                item.write_cpu.as_ref(),
+            ),
+            AssertedOp::Overwrite => (item.overwrite_tput_mbps, None, item.overwrite_cpu.as_ref()),
+            AssertedOp::Read => (
+                Some(item.read_tput_mbps),
+                item.read_latency.as_ref(),
+                item.read_cpu.as_ref(),
+            ),
+        };
+        let mut result = |limit: String, failure: Option<String>| {
+            results.push(AssertionResult {
+                assertion: name.to_string(),
+                case: format!("{row}: {limit}"),
+                failure,
+            });
+        };
+        if let Some(min_mbps) = self.min_mbps {
+            result(
+                format!("throughput >= {min_mbps} MiB/s"),
+                match mbps {
+                    Some(mbps) if mbps >= min_mbps => None,
+                    Some(mbps) => Some(format!("{mbps:.1} MiB/s")),
+                    None => Some("throughput not measured".to_string()),
+                },
+            );
+        }
+        if let Some(max_p99_us) = self.max_p99_us {
+            result(
+                format!("p99 latency <= {max_p99_us} us"),
+                match latency {
+                    Some(LatencySummary { p99_us, .. }) if *p99_us <= max_p99_us => None,
+                    Some(latency) => Some(format!("p99 {:.0} us", latency.p99_us)),
+                    None => Some("latency not measured".to_string()),
+                },
+            );
+        }
+        if let Some(max_cpu) = self.max_cpu_secs_per_gib {
+            result(
+                format!("CPU <= {max_cpu} sec/GiB"),
+                match cpu {
+                    Some(CpuUsage {
+                        cpu_secs_per_gib, ..
+                    }) if *cpu_secs_per_gib <= max_cpu => None,
+                    Some(cpu) => Some(format!("{:.3} CPU sec/GiB", cpu.cpu_secs_per_gib)),
+                    None => Some("CPU usage not measured".to_string()),
+                },
+            );
+        }
+    }
+}
+
+// One test suite per assertion, one test case per row and limit.
+fn junit_xml(results: &[AssertionResult], failures: usize) -> String {
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        xml,
+        r#"<testsuites name="io-experiments" tests="{tests}" failures="{failures}">"#,
+        tests = results.len()
+    )
+    .unwrap();
+    let mut start = 0;
+    while start < results.len() {
+        let assertion = &results[start].assertion;
+        let end = start
+            + results[start..]
+                .iter()
+                .take_while(|result| result.assertion == *assertion)
+                .count();
+        let suite = &results[start..end];
+        writeln!(
+            xml,
+            r#"  <testsuite name="{name}" tests="{tests}" failures="{failures}">"#,
+            name = escape(assertion),
+            tests = suite.len(),
+            failures = suite
+                .iter()
+                .filter(|result| result.failure.is_some())
+                .count(),
+        )
+        .unwrap();
+        for result in suite {
+            let case = format!(
+                r#"<testcase classname="{classname}" name="{name}""#,
+                classname = escape(assertion),
+                name = escape(&result.case)
+            );
+            match &result.failure {
+                Some(failure) => writeln!(
+                    xml,
+                    "    {case}>\n      <failure message=\"{failure}\"/>\n    </testcase>",
+                    failure = escape(failure)
+                ),
+                None => writeln!(xml, "    {case}/>"),
+            }
+            .unwrap();
+        }
+        writeln!(xml, "  </testsuite>").unwrap();
+        start = end;
+    }
+    writeln!(xml, "</testsuites>").unwrap();
+    xml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bench_settings::{FileLayout, IoMethodSettings},
+        direct_io_uring::DirectUring,
+    };
+
+    fn item(sequence: IoSequence, read_tput_mbps: f64, p99_us: f64) -> ReportItem {
+        let mut item = ReportItem::new(
+            IoMethodSettings::DirectUring(DirectUring {
+                block_size: 65536,
+                concurrency: 128,
+                vectored: None,
+            }),
+            sequence,
+            Some(Preallocate::Sparse),
+            FileLayout::Single,
+        );
+        item.write_tput_mbps = 1000.0;
+        item.read_tput_mbps = read_tput_mbps;
+        item.read_latency = Some(LatencySummary {
+            count: 2048,
+            mean_us: p99_us / 2.0,
+            p50_us: p99_us / 2.0,
+            p99_us,
+            max_us: p99_us * 2.0,
+        });
+        item.read_cpu = Some(CpuUsage {
+            cpu_secs_per_gib: 0.1,
+            ..Default::default()
+        });
+        item
+    }
+
+    // the random read gate of assertions-lite.json
+    fn random_read_gate() -> Vec<Assertion> {
+        let mut assertions: Vec<Assertion> =
+            serde_json::from_str(include_str!("../assertions-lite.json")).unwrap();
+        assertions.truncate(1);
+        assert_eq!(assertions[0].sequence, Some(IoSequence::Random));
+        assertions
+    }
+
+    #[test]
+    fn checks_only_matching_rows() {
+        let gate = random_read_gate();
+        let fast_sequential = item(IoSequence::Sequential, 3000.0, 1000.0);
+        let report = Report {
+            items: vec![
+                fast_sequential.clone(),
+                item(IoSequence::Random, 800.0, 5000.0),
+            ],
+            ..Default::default()
+        };
+        assert!(check_assertions(&report, &gate, None));
+        // a fast sequential row doesn't hide a slow random one
+        let report = Report {
+            items: vec![fast_sequential, item(IoSequence::Random, 400.0, 5000.0)],
+            ..Default::default()
+        };
+        assert!(!check_assertions(&report, &gate, None));
+        let report = Report {
+            items: vec![item(IoSequence::Random, 800.0, 30000.0)],
+            ..Default::default()
+        };
+        assert!(!check_assertions(&report, &gate, None));
+    }
+
+    #[test]
+    fn fails_without_matching_rows() {
+        let report = Report {
+            items: vec![item(IoSequence::Sequential, 3000.0, 1000.0)],
+            ..Default::default()
+        };
+        assert!(!check_assertions(&report, &random_read_gate(), None));
+    }
+
+    #[test]
+    fn fails_without_latency() {
+        let mut item = item(IoSequence::Random, 800.0, 5000.0);
+        item.read_latency = None;
+        let report = Report {
+            items: vec![item],
+            ..Default::default()
+        };
+        assert!(!check_assertions(&report, &random_read_gate(), None));
+    }
+
+    #[test]
+    fn junit() {
+        let results = [
+            AssertionResult {
+                assertion: "random <read>".to_string(),
+                case: "row: throughput >= 500 MiB/s".to_string(),
+                failure: None,
+            },
+            AssertionResult {
+                assertion: "random <read>".to_string(),
+                case: "row: p99 latency <= 20000 us".to_string(),
+                failure: Some("p99 \"30000\" us".to_string()),
+            },
+        ];
+        assert_eq!(
+            junit_xml(&results, 1),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="io-experiments" tests="2" failures="1">
+  <testsuite name="random &lt;read&gt;" tests="2" failures="1">
+    <testcase classname="random &lt;read&gt;" name="row: throughput &gt;= 500 MiB/s"/>
+    <testcase classname="random &lt;read&gt;" name="row: p99 latency &lt;= 20000 us">
+      <failure message="p99 &quot;30000&quot; us"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -1,22 +1,114 @@
+use std::{fmt::Write, path::Path};
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    buffered_io::Buffered, buffered_io_uring::BufferedUring, direct_async_io::DirectAsync,
-    direct_io::Direct, direct_io_uring::DirectUring, run_benchmark::RunCommand,
+    IoMethod,
+    assertions::Assertion,
+    buffered_io::Buffered,
+    buffered_io_uring::BufferedUring,
+    direct_async_io::DirectAsync,
+    direct_io::Direct,
+    direct_io_uring::DirectUring,
+    io_data::{Vectored, Workload},
+    pacing::OpenLoopSettings,
+    replay::{ReplaySettings, TraceOp},
+    rw_flags::RwFlag,
+    small_files::SmallFilesSettings,
+    steady_state::SteadyStateSettings,
 };
 
-pub fn read_bench_settings(args: &RunCommand) -> BenchSettings {
-    let json = std::fs::read(&args.settings_file).unwrap();
-    serde_json::from_slice(&json).unwrap()
+pub fn read_bench_settings(settings_file: &Path) -> BenchSettings {
+    let json = std::fs::read(settings_file).unwrap();
+    let settings: BenchSettings = serde_json::from_slice(&json).unwrap();
+    for method in settings
+        .methods
+        .iter()
+        .chain(settings.replay.iter().flat_map(|replay| &replay.methods))
+    {
+        method.validate();
+    }
+    for small_files in &settings.small_files {
+        small_files.validate();
+    }
+    settings
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BenchSettings {
     pub file_size: u64,
+    #[serde(default = "default_preallocate")]
+    pub preallocate: Vec<Preallocate>,
+    #[serde(default = "default_file_layouts")]
+    pub file_layouts: Vec<FileLayout>,
+    #[serde(default)]
     pub methods: Vec<IoMethodSettings>,
+    #[serde(default)]
+    pub small_files: Vec<SmallFilesSettings>,
+    #[serde(default)]
+    pub open_loop: Option<OpenLoopSettings>,
+    #[serde(default)]
+    pub steady_state: Option<SteadyStateSettings>,
+    // per-operation latency from an extra write and read pass after the throughput passes
+    #[serde(default)]
+    pub measure_latency: bool,
+    // performance budgets checked after the run, see `run --assertions-file`
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+    #[serde(default)]
+    pub replay: Vec<ReplaySettings>,
+    // temporarily replaces read_ahead_kb of the device backing each target
+    #[serde(default)]
+    pub readahead_kb: Option<u32>,
+    #[serde(default = "default_sample_interval_ms")]
+    pub sample_interval_ms: u64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+fn default_preallocate() -> Vec<Preallocate> {
+    vec![Preallocate::Sparse]
+}
+
+fn default_file_layouts() -> Vec<FileLayout> {
+    vec![FileLayout::Single]
+}
+
+fn default_sample_interval_ms() -> u64 {
+    100
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Preallocate {
+    #[default]
+    Sparse,
+    Fallocate,
+    FallocateKeepSize,
+    ZeroFill,
+    ReuseExisting,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileLayout {
+    #[default]
+    Single,
+    FilePerWorker,
+    RandomFile {
+        files: u32,
+    },
+}
+
+impl FileLayout {
+    pub fn file_count(&self, concurrency: u32) -> u32 {
+        match self {
+            FileLayout::Single => 1,
+            FileLayout::FilePerWorker => concurrency,
+            FileLayout::RandomFile { files } => *files,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum IoMethodSettings {
     Buffered(Buffered),
@@ -36,4 +128,175 @@ impl IoMethodSettings {
             IoMethodSettings::DirectUring(direct_uring) => direct_uring.block_size,
         }
     }
+
+    pub(crate) fn concurrency(&self) -> u32 {
+        match self {
+            IoMethodSettings::Buffered(_) => 1,
+            IoMethodSettings::BufferedUring(buffered_uring) => buffered_uring.concurrency,
+            IoMethodSettings::Direct(_) => 1,
+            IoMethodSettings::DirectAsync(direct_async) => direct_async.concurrency,
+            IoMethodSettings::DirectUring(direct_uring) => direct_uring.concurrency,
+        }
+    }
+
+    // the serde tag
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            IoMethodSettings::Buffered(_) => "buffered",
+            IoMethodSettings::BufferedUring(_) => "buffered_uring",
+            IoMethodSettings::Direct(_) => "direct",
+            IoMethodSettings::DirectAsync(_) => "direct_async",
+            IoMethodSettings::DirectUring(_) => "direct_uring",
+        }
+    }
+
+    pub(crate) fn vectored(&self) -> Option<Vectored> {
+        match self {
+            IoMethodSettings::Buffered(buffered) => buffered.vectored,
+            IoMethodSettings::BufferedUring(buffered_uring) => buffered_uring.vectored,
+            IoMethodSettings::Direct(direct) => direct.vectored,
+            IoMethodSettings::DirectAsync(_) => None,
+            IoMethodSettings::DirectUring(direct_uring) => direct_uring.vectored,
+        }
+    }
+
+    pub(crate) fn rw_flags(&self) -> &[RwFlag] {
+        match self {
+            IoMethodSettings::Buffered(buffered) => &buffered.rw_flags,
+            IoMethodSettings::Direct(direct) => &direct.rw_flags,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn validate(&self) {
+        if let Some(vectored) = self.vectored() {
+            assert!(
+                vectored.iovecs > 0
+                    && vectored.iovecs as u64 * vectored.segment_size as u64
+                        == self.block_size() as u64,
+                "{name}: iovecs * segment_size must equal the block size {block_size}",
+                name = self.name(),
+                block_size = self.block_size()
+            );
+        }
+    }
+
+    // The serde tag and the variant, e.g. "direct_uring 16x4096" for 16 segments of 4 KiB or
+    // "direct nowait+hipri", so that charts and pivots keep the variants of a method apart.
+    pub(crate) fn label(&self) -> String {
+        let mut label = self.name().to_string();
+        if let Some(vectored) = self.vectored() {
+            write!(
+                label,
+                " {iovecs}x{segment_size}",
+                iovecs = vectored.iovecs,
+                segment_size = vectored.segment_size
+            )
+            .unwrap();
+        }
+        if !self.rw_flags().is_empty() {
+            write!(label, " {}", rw_flags_label(self.rw_flags())).unwrap();
+        }
+        label
+    }
+}
+
+// e.g. "nowait+hipri"
+pub fn rw_flags_label(rw_flags: &[RwFlag]) -> String {
+    rw_flags
+        .iter()
+        .map(|flag| format!("{flag:?}").to_lowercase())
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+impl IoMethod for IoMethodSettings {
+    fn write_file(&self, workload: &Workload) {
+        match self {
+            IoMethodSettings::Buffered(buffered) => buffered.write_file(workload),
+            IoMethodSettings::BufferedUring(buffered_uring) => buffered_uring.write_file(workload),
+            IoMethodSettings::Direct(direct) => direct.write_file(workload),
+            IoMethodSettings::DirectAsync(direct_async) => direct_async.write_file(workload),
+            IoMethodSettings::DirectUring(direct_uring) => direct_uring.write_file(workload),
+        }
+    }
+
+    fn read_file(&self, workload: &Workload) {
+        match self {
+            IoMethodSettings::Buffered(buffered) => buffered.read_file(workload),
+            IoMethodSettings::BufferedUring(buffered_uring) => buffered_uring.read_file(workload),
+            IoMethodSettings::Direct(direct) => direct.read_file(workload),
+            IoMethodSettings::DirectAsync(direct_async) => direct_async.read_file(workload),
+            IoMethodSettings::DirectUring(direct_uring) => direct_uring.read_file(workload),
+        }
+    }
+
+    fn replay(&self, workload: &Workload, trace: &[TraceOp]) {
+        match self {
+            IoMethodSettings::Buffered(buffered) => buffered.replay(workload, trace),
+            IoMethodSettings::BufferedUring(buffered_uring) => {
+                buffered_uring.replay(workload, trace)
+            }
+            IoMethodSettings::Direct(direct) => direct.replay(workload, trace),
+            IoMethodSettings::DirectAsync(direct_async) => direct_async.replay(workload, trace),
+            IoMethodSettings::DirectUring(direct_uring) => direct_uring.replay(workload, trace),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn method(json: &str) -> serde_json::Result<IoMethodSettings> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn vectored_settings() {
+        let m = method(
+            r#"{ "type": "direct", "block_size": 65536,
+                 "vectored": { "iovecs": 16, "segment_size": 4096 } }"#,
+        )
+        .unwrap();
+        m.validate();
+        assert_eq!(
+            m.vectored(),
+            Some(Vectored {
+                iovecs: 16,
+                segment_size: 4096
+            })
+        );
+        assert_eq!(m.label(), "direct 16x4096");
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(method(&json).unwrap(), m);
+        let contiguous = method(r#"{ "type": "direct", "block_size": 65536 }"#).unwrap();
+        assert_eq!(contiguous.vectored(), None);
+        assert!(
+            !serde_json::to_string(&contiguous)
+                .unwrap()
+                .contains("vectored")
+        );
+    }
+
+    #[test]
+    fn partial_vectored_settings() {
+        let error = method(
+            r#"{ "type": "buffered_uring", "block_size": 65536, "concurrency": 8,
+                 "vectored": { "iovecs": 16 } }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("segment_size"), "{error}");
+    }
+
+    #[test]
+    #[should_panic(expected = "iovecs * segment_size must equal the block size 65536")]
+    fn vectored_size_mismatch() {
+        method(
+            r#"{ "type": "direct_uring", "block_size": 65536, "concurrency": 8,
+                 "vectored": { "iovecs": 16, "segment_size": 512 } }"#,
+        )
+        .unwrap()
+        .validate();
+    }
 }
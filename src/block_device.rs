@@ -0,0 +1,105 @@
+use std::{
+    fs::File,
+    os::{fd::AsRawFd, unix::fs::FileTypeExt},
+    path::Path,
+};
+
+use crate::bench_settings::{BenchSettings, FileLayout};
+
+// _IO(0x12, 104), _IO(0x12, 123) and _IOR(0x12, 114, size_t) from linux/fs.h
+const BLKSSZGET: u64 = 0x1268;
+const BLKPBSZGET: u64 = 0x127b;
+const BLKGETSIZE64: u64 = 0x80081272;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockDeviceInfo {
+    pub size: u64,
+    pub logical_block_size: u32,
+    pub physical_block_size: u32,
+}
+
+pub fn is_block_device(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_block_device())
+}
+
+pub fn block_device_info(path: &Path) -> BlockDeviceInfo {
+    let file = File::open(path).unwrap();
+    let fd = file.as_raw_fd();
+    let mut size: u64 = 0;
+    let mut logical_block_size: libc::c_int = 0;
+    let mut physical_block_size: libc::c_uint = 0;
+    // SAFETY: fd is valid and each ioctl writes into a value of the type it expects
+    unsafe {
+        check_ioctl(
+            libc::ioctl(fd, BLKGETSIZE64 as _, &mut size),
+            "BLKGETSIZE64",
+        );
+        check_ioctl(
+            libc::ioctl(fd, BLKSSZGET as _, &mut logical_block_size),
+            "BLKSSZGET",
+        );
+        check_ioctl(
+            libc::ioctl(fd, BLKPBSZGET as _, &mut physical_block_size),
+            "BLKPBSZGET",
+        );
+    }
+    BlockDeviceInfo {
+        size,
+        logical_block_size: logical_block_size as u32,
+        physical_block_size,
+    }
+}
+
+fn check_ioctl(rc: libc::c_int, name: &str) {
+    if rc != 0 {
+        panic!("{name}: {}", std::io::Error::last_os_error());
+    }
+}
+
+pub fn check_settings(path: &Path, info: &BlockDeviceInfo, settings: &BenchSettings) {
+    println!(
+        "block device {path}: {size} bytes, logical block {logical}, physical block {physical}",
+        path = path.display(),
+        size = info.size,
+        logical = info.logical_block_size,
+        physical = info.physical_block_size,
+    );
+    assert!(
+        settings.file_size <= info.size,
+        "file_size {file_size} exceeds the size of {path}",
+        file_size = settings.file_size,
+        path = path.display(),
+    );
+    // buffers come from aligned_vec, which aligns to 4096
+    assert!(info.logical_block_size <= 4096);
+    for m in &settings.methods {
+        assert_eq!(
+            m.block_size() % info.logical_block_size,
+            0,
+            "{m:?}: block size is not a multiple of the logical block size of {path}",
+            path = path.display(),
+        );
+        if m.block_size() % info.physical_block_size != 0 {
+            println!(
+                "warning: {m:?}: block size is not a multiple of the physical block size of {path}",
+                path = path.display(),
+            );
+        }
+    }
+    for r in &settings.replay {
+        assert_eq!(
+            r.align % info.logical_block_size,
+            0,
+            "{trace_file}: align is not a multiple of the logical block size of {path}",
+            trace_file = r.trace_file.display(),
+            path = path.display(),
+        );
+    }
+    assert!(
+        settings
+            .file_layouts
+            .iter()
+            .all(|layout| *layout == FileLayout::Single),
+        "only the single file layout is supported on block devices"
+    );
+}
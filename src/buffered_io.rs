@@ -1,47 +1,105 @@
 use std::{fs::OpenOptions, io::Write, os::unix::fs::FileExt};
 
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    IoMethod, IoSequence,
-    io_data::{access_seq, aligned_vec, buf_data},
+    IoMethod, IoOp,
+    io_data::{Vectored, Workload, aligned_vec, buf_data, segmented_buf},
+    replay::TraceOp,
+    rw_flags::RwFlag,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Buffered {
     pub block_size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vectored: Option<Vectored>,
+    // use preadv2/pwritev2 with these flags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rw_flags: Vec<RwFlag>,
 }
 
 impl IoMethod for Buffered {
-    fn write_file(&self, path: &std::path::Path, file_size: u64, sequence: crate::IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
+    fn write_file(&self, workload: &Workload) {
         let buf = buf_data(self.block_size as usize);
-        let num_pages = file_size / self.block_size as u64;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .open(path)
-            .unwrap();
-        for page_idx in access_seq(sequence, num_pages) {
-            file.write_all_at(&buf, page_idx * self.block_size as u64)
-                .unwrap();
+        let segmented = segmented_buf(self.vectored, &self.rw_flags, self.block_size, true);
+        let mut files = workload
+            .paths
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .open(path)
+                    .unwrap()
+            })
+            .collect_vec();
+        for queue in workload.access_queues(self.block_size) {
+            for (file_idx, page_idx) in queue {
+                let issue_time = workload.wait_issue();
+                let offset = page_idx * self.block_size as u64;
+                match &segmented {
+                    Some(segmented) => segmented.write_at(&files[file_idx], offset, &self.rw_flags),
+                    None => files[file_idx].write_all_at(&buf, offset).unwrap(),
+                }
+                workload.complete(IoOp::Write, self.block_size as u64, issue_time);
+            }
+        }
+        for file in &mut files {
+            file.flush().unwrap();
+            file.sync_all().unwrap();
         }
-        file.flush().unwrap();
-        file.sync_all().unwrap();
     }
 
-    fn read_file(&self, path: &std::path::Path, file_size: u64, sequence: IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
+    fn read_file(&self, workload: &Workload) {
         let mut buf = aligned_vec(self.block_size as usize);
-        let num_pages = file_size / self.block_size as u64;
+        let mut segmented = segmented_buf(self.vectored, &self.rw_flags, self.block_size, false);
+        let files = workload
+            .paths
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .open(path)
+                    .unwrap()
+            })
+            .collect_vec();
+        for file in &files {
+            workload.advise(file);
+        }
+        for queue in workload.access_queues(self.block_size) {
+            for (file_idx, page_idx) in queue {
+                let issue_time = workload.wait_issue();
+                let offset = page_idx * self.block_size as u64;
+                match &mut segmented {
+                    Some(segmented) => segmented.read_at(&files[file_idx], offset, &self.rw_flags),
+                    None => files[file_idx].read_exact_at(&mut buf, offset).unwrap(),
+                }
+                workload.complete(IoOp::Read, self.block_size as u64, issue_time);
+            }
+        }
+    }
+
+    fn replay(&self, workload: &Workload, trace: &[TraceOp]) {
+        let max_len = trace.iter().map(|op| op.len).max().unwrap_or(0);
+        let mut buf = buf_data(max_len as usize);
         let file = OpenOptions::new()
             .write(true)
             .read(true)
-            .open(path)
+            .open(&workload.paths[0])
             .unwrap();
-        for page_idx in access_seq(sequence, num_pages) {
-            file.read_exact_at(&mut buf, page_idx * self.block_size as u64)
-                .unwrap();
+        workload.advise(&file);
+        for op in trace {
+            let issue_time = workload.wait_issue();
+            let buf = &mut buf[..op.len as usize];
+            match op.op {
+                IoOp::Read => file.read_exact_at(buf, op.offset).unwrap(),
+                IoOp::Write => file.write_all_at(buf, op.offset).unwrap(),
+            }
+            workload.complete(op.op, op.len as u64, issue_time);
         }
+        file.sync_all().unwrap();
     }
 }
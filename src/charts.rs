@@ -0,0 +1,401 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use minijinja::{
+    context,
+    value::{Value, ViaDeserialize},
+};
+
+use crate::{IoSequence, ReportItem, sampler::ThroughputSample};
+
+const SPARKLINE_WIDTH: f64 = 240.0;
+const SPARKLINE_HEIGHT: f64 = 48.0;
+
+// Throughput over time of one pass as a small inline SVG, scaled to its own peak.
+pub fn sparkline(samples: ViaDeserialize<Vec<ThroughputSample>>) -> Value {
+    let samples = &*samples;
+    let Some(last) = samples.last() else {
+        return Value::from("");
+    };
+    let max_t = last.t_secs.max(f64::MIN_POSITIVE);
+    let max_mbps = samples
+        .iter()
+        .map(|sample| sample.mbps)
+        .fold(0.0, f64::max)
+        .max(f64::MIN_POSITIVE);
+    let mut points = String::new();
+    let mut prev_t = 0.0;
+    // each sample covers the interval since the previous one, so draw it as a step
+    for sample in samples {
+        let y = SPARKLINE_HEIGHT - sample.mbps / max_mbps * (SPARKLINE_HEIGHT - 2.0);
+        for t in [prev_t, sample.t_secs] {
+            write!(points, "{:.1},{:.1} ", t / max_t * SPARKLINE_WIDTH, y).unwrap();
+        }
+        prev_t = sample.t_secs;
+    }
+    Value::from_safe_string(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}"><title>peak {max_mbps:.0} MiB/s over {max_t:.2} s</title><rect width="{w}" height="{h}" fill="#f8f8f8"/><polyline points="{points}" fill="none" stroke="#1f77b4" stroke-width="1.5"/><text x="2" y="10" font-size="9" fill="#555">{max_mbps:.0} MiB/s</text><text x="{w}" y="{h}" dy="-2" font-size="9" fill="#555" text-anchor="end">{max_t:.2} s</text></svg>"##,
+        w = SPARKLINE_WIDTH,
+        h = SPARKLINE_HEIGHT,
+        points = points.trim_end(),
+    ))
+}
+
+const CHART_WIDTH: f64 = 720.0;
+const CHART_HEIGHT: f64 = 320.0;
+const MARGIN_LEFT: f64 = 64.0;
+const MARGIN_RIGHT: f64 = 220.0;
+const MARGIN_TOP: f64 = 16.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+const PALETTE: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+struct Series {
+    label: String,
+    dashed: bool,
+    points: Vec<(f64, f64)>,
+}
+
+// Charts for the HTML report, as `{title, svg}` objects in display order. Rows that differ only in
+// settings that aren't on an axis or in the legend are reduced to their best throughput.
+pub fn report_charts(items: &[ReportItem]) -> Vec<Value> {
+    let mut charts = Vec::new();
+    for (op, tput) in [
+        (
+            "Write",
+            (|item| item.write_tput_mbps) as fn(&ReportItem) -> f64,
+        ),
+        ("Read", |item| item.read_tput_mbps),
+    ] {
+        let series = best_series(
+            items,
+            |item| (source(item), item.method.label(), item.sequence),
+            |item| item.method.block_size(),
+            tput,
+        );
+        if !series.is_empty() {
+            charts.push(chart(
+                format!("{op} throughput vs block size"),
+                line_chart("block size, bytes", &series),
+            ));
+        }
+    }
+    for (op, tput) in [
+        (
+            "Write",
+            (|item| item.write_tput_mbps) as fn(&ReportItem) -> f64,
+        ),
+        ("Read", |item| item.read_tput_mbps),
+    ] {
+        let series = best_series(
+            items,
+            |item| {
+                (
+                    source(item),
+                    format!("{} bs{}", item.method.label(), item.method.block_size()),
+                    item.sequence,
+                )
+            },
+            |item| item.method.concurrency(),
+            tput,
+        )
+        .into_iter()
+        .filter(|series| series.points.len() >= 2)
+        .collect::<Vec<_>>();
+        if !series.is_empty() {
+            charts.push(chart(
+                format!("{op} throughput vs concurrency"),
+                line_chart("concurrency", &series),
+            ));
+        }
+    }
+    if !items.is_empty() {
+        charts.push(chart(
+            "Best write and read throughput by sequence".to_string(),
+            bar_chart(items),
+        ));
+    }
+    charts
+}
+
+// Machine and target, whichever are set, to tell series of merged reports apart.
+fn source(item: &ReportItem) -> Option<String> {
+    match (&item.machine, &item.target) {
+        (Some(machine), Some(target)) => Some(format!("{machine} {target}")),
+        (machine, target) => machine.clone().or_else(|| target.clone()),
+    }
+}
+
+fn chart(title: String, svg: String) -> Value {
+    context! {
+        title,
+        svg => Value::from_safe_string(svg),
+    }
+}
+
+fn best_series<K: Ord + Clone + SeriesKey>(
+    items: &[ReportItem],
+    key: impl Fn(&ReportItem) -> K,
+    x: impl Fn(&ReportItem) -> u32,
+    y: fn(&ReportItem) -> f64,
+) -> Vec<Series> {
+    let mut best = BTreeMap::<K, BTreeMap<u32, f64>>::new();
+    for item in items {
+        let point = best
+            .entry(key(item))
+            .or_default()
+            .entry(x(item))
+            .or_default();
+        *point = point.max(y(item));
+    }
+    best.into_iter()
+        .map(|(key, points)| Series {
+            label: key.label(),
+            dashed: key.sequence() == IoSequence::Random,
+            points: points.into_iter().map(|(x, y)| (x as f64, y)).collect(),
+        })
+        .collect()
+}
+
+trait SeriesKey {
+    fn label(&self) -> String;
+    fn sequence(&self) -> IoSequence;
+}
+
+impl<M: std::fmt::Display> SeriesKey for (Option<String>, M, IoSequence) {
+    fn label(&self) -> String {
+        match &self.0 {
+            Some(target) => format!("{target} {} {:?}", self.1, self.2),
+            None => format!("{} {:?}", self.1, self.2),
+        }
+    }
+
+    fn sequence(&self) -> IoSequence {
+        self.2
+    }
+}
+
+// Log2 x axis since block sizes and concurrency are powers of two in practice.
+fn line_chart(x_label: &str, series: &[Series]) -> String {
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    // grow with the legend so every series keeps its entry
+    let height = CHART_HEIGHT.max(MARGIN_TOP + 16.0 * series.len() as f64 + 8.0);
+    let plot_height = height - MARGIN_TOP - MARGIN_BOTTOM;
+    let xs = series
+        .iter()
+        .flat_map(|series| series.points.iter().map(|&(x, _)| x.max(1.0).log2()));
+    let min_x = xs.clone().fold(f64::MAX, f64::min);
+    let max_x = xs.fold(f64::MIN, f64::max).max(min_x + 1.0);
+    let max_y = nice_max(
+        series
+            .iter()
+            .flat_map(|series| series.points.iter().map(|&(_, y)| y))
+            .fold(0.0, f64::max),
+    );
+    let sx = |x: f64| MARGIN_LEFT + (x.max(1.0).log2() - min_x) / (max_x - min_x) * plot_width;
+    let sy = |y: f64| MARGIN_TOP + plot_height - y / max_y * plot_height;
+
+    let mut svg = svg_start(height);
+    for i in 0..=4 {
+        let y = max_y * i as f64 / 4.0;
+        write!(
+            svg,
+            r##"<line x1="{x1}" x2="{x2}" y1="{py:.1}" y2="{py:.1}" stroke="#ddd"/><text x="{tx}" y="{py:.1}" dy="3" font-size="10" text-anchor="end">{y}</text>"##,
+            x1 = MARGIN_LEFT,
+            x2 = MARGIN_LEFT + plot_width,
+            py = sy(y),
+            tx = MARGIN_LEFT - 4.0,
+        )
+        .unwrap();
+    }
+    let mut x = min_x.ceil();
+    while x <= max_x {
+        let value = 2f64.powf(x);
+        write!(
+            svg,
+            r##"<line x1="{px:.1}" x2="{px:.1}" y1="{y1}" y2="{y2}" stroke="#eee"/><text x="{px:.1}" y="{y2}" dy="12" font-size="10" text-anchor="middle">{label}</text>"##,
+            px = sx(value),
+            y1 = MARGIN_TOP,
+            y2 = MARGIN_TOP + plot_height,
+            label = format_power_of_two(value),
+        )
+        .unwrap();
+        x += 1.0;
+    }
+    write!(
+        svg,
+        r##"<text x="{x}" y="{y}" font-size="11" text-anchor="middle">{x_label}</text><text x="12" y="{ym}" font-size="11" text-anchor="middle" transform="rotate(-90 12 {ym})">MiB/s</text>"##,
+        x = MARGIN_LEFT + plot_width / 2.0,
+        y = height - 4.0,
+        ym = MARGIN_TOP + plot_height / 2.0,
+    )
+    .unwrap();
+    for (i, series) in series.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let dash = if series.dashed {
+            r#" stroke-dasharray="5 3""#
+        } else {
+            ""
+        };
+        let points = series
+            .points
+            .iter()
+            .map(|&(x, y)| format!("{:.1},{:.1}", sx(x), sy(y)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(
+            svg,
+            r#"<polyline points="{points}" fill="none" stroke="{color}" stroke-width="2"{dash}/>"#
+        )
+        .unwrap();
+        for &(x, y) in &series.points {
+            write!(
+                svg,
+                r#"<circle cx="{cx:.1}" cy="{cy:.1}" r="3" fill="{color}"><title>{label}: {y:.0} MiB/s at {x}</title></circle>"#,
+                cx = sx(x),
+                cy = sy(y),
+                label = escape(&series.label),
+            )
+            .unwrap();
+        }
+        let ly = MARGIN_TOP + 8.0 + i as f64 * 16.0;
+        write!(
+            svg,
+            r#"<line x1="{x1}" x2="{x2}" y1="{ly}" y2="{ly}" stroke="{color}" stroke-width="2"{dash}/><text x="{tx}" y="{ly}" dy="4" font-size="11">{label}</text>"#,
+            x1 = CHART_WIDTH - MARGIN_RIGHT + 12.0,
+            x2 = CHART_WIDTH - MARGIN_RIGHT + 32.0,
+            tx = CHART_WIDTH - MARGIN_RIGHT + 36.0,
+            label = escape(&series.label),
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+// Horizontal bars, one write and one read bar per method, grouped by sequence.
+fn bar_chart(items: &[ReportItem]) -> String {
+    let mut best = BTreeMap::<(IoSequence, Option<String>, String), (f64, f64)>::new();
+    for item in items {
+        let bars = best
+            .entry((item.sequence, source(item), item.method.label()))
+            .or_default();
+        bars.0 = bars.0.max(item.write_tput_mbps);
+        bars.1 = bars.1.max(item.read_tput_mbps);
+    }
+    let max_x = nice_max(
+        best.values()
+            .map(|&(write, read)| write.max(read))
+            .fold(0.0, f64::max),
+    );
+    let bar_height = 10.0;
+    let label_width = 200.0;
+    let plot_width = CHART_WIDTH - label_width - 80.0;
+    let groups = best.keys().map(|key| key.0).collect::<BTreeSet<_>>().len();
+    let height = MARGIN_TOP + MARGIN_BOTTOM + (best.len() as f64) * 28.0 + groups as f64 * 20.0;
+    let sx = |x: f64| label_width + x / max_x * plot_width;
+
+    let mut svg = svg_start(height);
+    for i in 0..=4 {
+        let x = max_x * i as f64 / 4.0;
+        write!(
+            svg,
+            r##"<line x1="{px:.1}" x2="{px:.1}" y1="{MARGIN_TOP}" y2="{y2}" stroke="#ddd"/><text x="{px:.1}" y="{y2}" dy="12" font-size="10" text-anchor="middle">{x}</text>"##,
+            px = sx(x),
+            y2 = height - MARGIN_BOTTOM,
+        )
+        .unwrap();
+    }
+    let mut y = MARGIN_TOP;
+    let mut prev_sequence = None;
+    for (&(sequence, ref target, ref method), &(write, read)) in &best {
+        if prev_sequence != Some(sequence) {
+            y += 16.0;
+            write!(
+                svg,
+                r#"<text x="4" y="{y}" font-size="12" font-weight="bold">{sequence:?}</text>"#
+            )
+            .unwrap();
+            y += 4.0;
+            prev_sequence = Some(sequence);
+        }
+        let label = match target {
+            Some(target) => format!("{target} {method}"),
+            None => method.to_string(),
+        };
+        write!(
+            svg,
+            r#"<text x="{tx}" y="{ty}" font-size="11" text-anchor="end">{label}</text>"#,
+            tx = label_width - 6.0,
+            ty = y + bar_height + 3.0,
+            label = escape(&label),
+        )
+        .unwrap();
+        for (i, (op, value)) in [("write", write), ("read", read)].into_iter().enumerate() {
+            let by = y + i as f64 * (bar_height + 2.0);
+            write!(
+                svg,
+                r#"<rect x="{label_width}" y="{by}" width="{w:.1}" height="{bar_height}" fill="{color}"><title>{op}: {value:.0} MiB/s</title></rect><text x="{tx:.1}" y="{by}" dy="9" font-size="9">{value:.0}</text>"#,
+                w = sx(value) - label_width,
+                color = PALETTE[i],
+                tx = sx(value) + 3.0,
+            )
+            .unwrap();
+        }
+        y += 28.0;
+    }
+    write!(
+        svg,
+        r#"<rect x="{x}" y="4" width="10" height="10" fill="{write}"/><text x="{tx}" y="13" font-size="11">write</text><rect x="{x2}" y="4" width="10" height="10" fill="{read}"/><text x="{tx2}" y="13" font-size="11">read</text><text x="{xm}" y="{yb}" font-size="11" text-anchor="middle">MiB/s</text></svg>"#,
+        x = CHART_WIDTH - 120.0,
+        tx = CHART_WIDTH - 106.0,
+        x2 = CHART_WIDTH - 60.0,
+        tx2 = CHART_WIDTH - 46.0,
+        write = PALETTE[0],
+        read = PALETTE[1],
+        xm = label_width + plot_width / 2.0,
+        yb = height - 8.0,
+    )
+    .unwrap();
+    svg
+}
+
+fn svg_start(height: f64) -> String {
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{CHART_WIDTH}" height="{height}" viewBox="0 0 {CHART_WIDTH} {height}" font-family="sans-serif"><rect width="{CHART_WIDTH}" height="{height}" fill="#fff"/>"##
+    )
+}
+
+// Rounds up to 1, 2 or 5 times a power of ten so the axis ticks are round numbers.
+fn nice_max(max: f64) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(max.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|step| step * magnitude)
+        .find(|&nice| nice >= max)
+        .unwrap()
+}
+
+fn format_power_of_two(value: f64) -> String {
+    let value = value.round() as u64;
+    if value >= 1 << 20 && value.is_multiple_of(1 << 20) {
+        format!("{}M", value >> 20)
+    } else if value >= 1 << 10 && value.is_multiple_of(1 << 10) {
+        format!("{}K", value >> 10)
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,442 @@
+use std::path::{Path, PathBuf};
+
+use minijinja::context;
+use serde::Serialize;
+
+use crate::{
+    IoSequence, ReportItem,
+    bench_settings::{FileLayout, IoMethodSettings, Preallocate},
+    report::read_report,
+    templates,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CompareCommand {
+    /// The first report is the baseline, every other one is compared against it
+    #[clap(value_parser, num_args = 2.., required = true)]
+    report_files: Vec<PathBuf>,
+    /// Throughput drop, in percent, that counts as a regression
+    #[clap(long, default_value_t = DEFAULT_THRESHOLD_PCT)]
+    threshold_pct: f64,
+    /// Significance level of the t-test, used when both rows have per-pass throughput
+    #[clap(long, default_value_t = DEFAULT_ALPHA)]
+    alpha: f64,
+    #[clap(long, value_parser)]
+    html_file: Option<PathBuf>,
+    #[clap(long, value_parser)]
+    markdown_file: Option<PathBuf>,
+}
+
+const DEFAULT_THRESHOLD_PCT: f64 = 5.0;
+const DEFAULT_ALPHA: f64 = 0.05;
+
+#[derive(Serialize)]
+struct Comparison {
+    label: String,
+    rows: Vec<ComparedRow>,
+    // rows of the baseline that this report doesn't have
+    missing: Vec<String>,
+    regressions: usize,
+}
+
+#[derive(Serialize)]
+struct ComparedRow {
+    name: String,
+    target: Option<String>,
+    method: IoMethodSettings,
+    sequence: IoSequence,
+    preallocate: Option<Preallocate>,
+    file_layout: FileLayout,
+    deltas: Vec<Delta>,
+}
+
+#[derive(Serialize)]
+struct Delta {
+    op: &'static str,
+    baseline_mbps: f64,
+    mbps: f64,
+    delta_mbps: f64,
+    delta_pct: f64,
+    p_value: Option<f64>,
+    regression: bool,
+}
+
+pub fn run_compare(command: &CompareCommand) {
+    let comparisons = compare_reports(command);
+
+    for comparison in &comparisons {
+        println!(
+            "{baseline} -> {label}",
+            baseline = label(&command.report_files[0]),
+            label = comparison.label
+        );
+        for row in &comparison.rows {
+            for delta in &row.deltas {
+                println!(
+                    "  {name} {op}: {baseline:.2} -> {mbps:.2} MiB/sec ({delta_pct:+.1}%{p_value}){regression}",
+                    name = row.name,
+                    op = delta.op,
+                    baseline = delta.baseline_mbps,
+                    mbps = delta.mbps,
+                    delta_pct = delta.delta_pct,
+                    p_value = delta
+                        .p_value
+                        .map_or(String::new(), |p| format!(", p={p:.3}")),
+                    regression = if delta.regression { " REGRESSION" } else { "" },
+                );
+            }
+        }
+        for name in &comparison.missing {
+            println!("  {name}: missing");
+        }
+    }
+
+    write_outputs(command, &comparisons);
+
+    let regressions = comparisons
+        .iter()
+        .map(|comparison| comparison.regressions)
+        .sum::<usize>();
+    if regressions > 0 {
+        println!("{regressions} regressions");
+        std::process::exit(1);
+    }
+}
+
+// Writes the HTML comparison of `report_files` against the first one with the default settings,
+// for pages that link to comparisons rather than gate on them.
+pub fn write_compare_html(report_files: Vec<PathBuf>, html_file: PathBuf) {
+    let command = CompareCommand {
+        report_files,
+        threshold_pct: DEFAULT_THRESHOLD_PCT,
+        alpha: DEFAULT_ALPHA,
+        html_file: Some(html_file),
+        markdown_file: None,
+    };
+    write_outputs(&command, &compare_reports(&command));
+}
+
+fn compare_reports(command: &CompareCommand) -> Vec<Comparison> {
+    let baseline = read_report(&command.report_files[0]).items;
+    command.report_files[1..]
+        .iter()
+        .map(|path| compare(&baseline, &read_report(path).items, label(path), command))
+        .collect()
+}
+
+fn write_outputs(command: &CompareCommand, comparisons: &[Comparison]) {
+    let ctx = context!(
+        baseline => label(&command.report_files[0]),
+        threshold_pct => command.threshold_pct,
+        alpha => command.alpha,
+        comparisons => comparisons,
+    );
+    for (file, template) in [
+        (&command.html_file, templates::COMPARE_HTML),
+        (&command.markdown_file, templates::COMPARE_MARKDOWN),
+    ] {
+        if let Some(file) = file {
+            let env = templates::environment(None, template);
+            let output = env.get_template("main").unwrap().render(&ctx).unwrap();
+            std::fs::write(file, output).unwrap();
+        }
+    }
+}
+
+fn label(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+// Rows match on everything except throughput, so reports with several targets compare target by
+// target.
+fn same_settings(a: &ReportItem, b: &ReportItem) -> bool {
+    a.machine == b.machine
+        && a.target == b.target
+        && a.method == b.method
+        && a.sequence == b.sequence
+        && a.preallocate == b.preallocate
+        && a.file_layout == b.file_layout
+}
+
+pub fn row_name(item: &ReportItem) -> String {
+    format!(
+        "{target}{method:?} {sequence:?} {preallocate:?} {file_layout:?}",
+        target = item
+            .target
+            .as_ref()
+            .map_or(String::new(), |target| format!("{target} ")),
+        method = item.method,
+        sequence = item.sequence,
+        preallocate = item.preallocate,
+        file_layout = item.file_layout,
+    )
+}
+
+fn compare(
+    baseline: &[ReportItem],
+    items: &[ReportItem],
+    label: String,
+    command: &CompareCommand,
+) -> Comparison {
+    let mut rows = Vec::new();
+    let mut missing = Vec::new();
+    for (i, base) in baseline.iter().enumerate() {
+        // a report may repeat the same settings, pair the repeats up in order
+        let repeat = baseline[..i]
+            .iter()
+            .filter(|prev| same_settings(base, prev))
+            .count();
+        let Some(item) = items
+            .iter()
+            .filter(|item| same_settings(base, item))
+            .nth(repeat)
+        else {
+            missing.push(row_name(base));
+            continue;
+        };
+        let mut deltas = Vec::new();
+        for (op, base_mbps, mbps, base_passes, passes) in [
+            (
+                "write",
+                Some(base.write_tput_mbps),
+                Some(item.write_tput_mbps),
+                &base.write_pass_mbps,
+                &item.write_pass_mbps,
+            ),
+            (
+                "overwrite",
+                base.overwrite_tput_mbps,
+                item.overwrite_tput_mbps,
+                &base.overwrite_pass_mbps,
+                &item.overwrite_pass_mbps,
+            ),
+            (
+                "read",
+                Some(base.read_tput_mbps),
+                Some(item.read_tput_mbps),
+                &base.read_pass_mbps,
+                &item.read_pass_mbps,
+            ),
+        ] {
+            // imported fio results only have one of write and read
+            let (Some(base_mbps), Some(mbps)) = (base_mbps, mbps) else {
+                continue;
+            };
+            if base_mbps == 0.0 || mbps == 0.0 {
+                continue;
+            }
+            let delta_pct = (mbps - base_mbps) / base_mbps * 100.0;
+            let p_value = welch_t_test(base_passes, passes);
+            deltas.push(Delta {
+                op,
+                baseline_mbps: base_mbps,
+                mbps,
+                delta_mbps: mbps - base_mbps,
+                delta_pct,
+                p_value,
+                regression: delta_pct < -command.threshold_pct
+                    && p_value.is_none_or(|p| p < command.alpha),
+            });
+        }
+        rows.push(ComparedRow {
+            name: row_name(base),
+            target: base.target.clone(),
+            method: base.method.clone(),
+            sequence: base.sequence,
+            preallocate: base.preallocate,
+            file_layout: base.file_layout,
+            deltas,
+        });
+    }
+    let regressions = rows
+        .iter()
+        .flat_map(|row| &row.deltas)
+        .filter(|delta| delta.regression)
+        .count();
+    Comparison {
+        label,
+        rows,
+        missing,
+        regressions,
+    }
+}
+
+// Two-sided p-value of Welch's t-test, None unless both sides have at least two passes.
+fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (mean_a, var_a) = mean_variance(a);
+    let (mean_b, var_b) = mean_variance(b);
+    let se_a = var_a / a.len() as f64;
+    let se_b = var_b / b.len() as f64;
+    if se_a + se_b == 0.0 {
+        return Some(if mean_a == mean_b { 1.0 } else { 0.0 });
+    }
+    let t = (mean_a - mean_b) / (se_a + se_b).sqrt();
+    let df = (se_a + se_b).powi(2)
+        / (se_a.powi(2) / (a.len() - 1) as f64 + se_b.powi(2) / (b.len() - 1) as f64);
+    Some(incomplete_beta(df / (df + t * t), df / 2.0, 0.5))
+}
+
+fn mean_variance(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+// Regularized incomplete beta function I_x(a, b), by the continued fraction from Numerical Recipes.
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // the continued fraction converges quickly only below the mean
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+// Lanczos approximation, g = 7
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn ln_gamma_known_values() {
+        assert_close(ln_gamma(1.0), 0.0);
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln());
+        // 9! = 362880
+        assert_close(ln_gamma(10.0), 362880f64.ln());
+        // below 0.5 by the reflection formula
+        assert_close(ln_gamma(0.1), 2.252_712_651_734_206);
+        assert_close(ln_gamma(100.5), 361.435_540_467_777_6);
+    }
+
+    #[test]
+    fn incomplete_beta_known_values() {
+        assert_eq!(incomplete_beta(0.0, 5.0, 0.5), 0.0);
+        assert_eq!(incomplete_beta(1.0, 5.0, 0.5), 1.0);
+        // I_x(1, 1) is the uniform distribution
+        assert_close(incomplete_beta(0.3, 1.0, 1.0), 0.3);
+        // the arcsine distribution is symmetric
+        assert_close(incomplete_beta(0.5, 0.5, 0.5), 0.5);
+        // two-sided p of t = 2 with 10 degrees of freedom
+        assert_close(
+            incomplete_beta(10.0 / 14.0, 5.0, 0.5),
+            0.073_388_034_770_720_76,
+        );
+        // both sides of the continued fraction's switch point agree
+        for x in [0.1, 0.5, 0.9] {
+            assert_close(
+                incomplete_beta(x, 3.0, 2.0),
+                1.0 - incomplete_beta(1.0 - x, 2.0, 3.0),
+            );
+        }
+    }
+
+    #[test]
+    fn welch_t_test_known_values() {
+        // equal variances and sizes: t = -2 with 10 degrees of freedom
+        let a = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let shift = 2.0 * (7.0f64 / 6.0).sqrt();
+        let b = a.map(|v| v + shift);
+        assert_close(welch_t_test(&a, &b).unwrap(), 0.073_388_034_770_720_76);
+        // t = -5 with 8 degrees of freedom
+        assert_close(
+            welch_t_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]).unwrap(),
+            0.001_052_825_793_365_424,
+        );
+    }
+
+    #[test]
+    fn welch_t_test_edge_cases() {
+        // too few passes for a variance
+        assert_eq!(welch_t_test(&[1.0], &[1.0, 2.0, 3.0]), None);
+        assert_eq!(welch_t_test(&[1.0, 2.0], &[]), None);
+        // no variance at all: the means are either equal or certainly different
+        assert_eq!(welch_t_test(&[5.0, 5.0, 5.0], &[5.0, 5.0]), Some(1.0));
+        assert_eq!(welch_t_test(&[5.0, 5.0, 5.0], &[6.0, 6.0]), Some(0.0));
+        // identical samples
+        let a = [100.0, 102.0, 98.0, 101.0];
+        assert_close(welch_t_test(&a, &a).unwrap(), 1.0);
+        // one side without variance: t = -sqrt(3) with 2 degrees of freedom, where the two-sided
+        // p is 1 - |t| / sqrt(t^2 + 2)
+        let p = welch_t_test(&[5.0, 5.0, 5.0], &[5.0, 6.0, 7.0]).unwrap();
+        assert_close(p, 1.0 - 3f64.sqrt() / 5f64.sqrt());
+    }
+}
@@ -0,0 +1,118 @@
+use std::mem::MaybeUninit;
+
+use serde::{Deserialize, Serialize};
+
+// CPU time and context switches spent by one pass of a measurement. `user_secs` and `sys_secs`
+// cover the whole process (RUSAGE_SELF), including the tokio workers and io_uring kernel threads
+// (SQPOLL and io-wq workers are threads of our process); `io_uring_kernel_secs` is the part of that
+// spent in io_uring threads that were still alive when the pass finished.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CpuUsage {
+    pub user_secs: f64,
+    pub sys_secs: f64,
+    pub thread_user_secs: f64,
+    pub thread_sys_secs: f64,
+    pub io_uring_kernel_secs: f64,
+    pub voluntary_ctx_switches: f64,
+    pub involuntary_ctx_switches: f64,
+    pub cpu_secs_per_gib: f64,
+}
+
+pub struct CpuSnapshot {
+    process: libc::rusage,
+    thread: libc::rusage,
+    io_uring_ticks: u64,
+}
+
+impl CpuSnapshot {
+    pub fn now() -> CpuSnapshot {
+        CpuSnapshot {
+            process: getrusage(libc::RUSAGE_SELF),
+            thread: getrusage(libc::RUSAGE_THREAD),
+            io_uring_ticks: io_uring_ticks(),
+        }
+    }
+
+    pub fn usage_since(&self, start: &CpuSnapshot) -> CpuUsage {
+        let secs =
+            |end: libc::timeval, start: libc::timeval| timeval_secs(end) - timeval_secs(start);
+        // SAFETY: sysconf has no preconditions
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        CpuUsage {
+            user_secs: secs(self.process.ru_utime, start.process.ru_utime),
+            sys_secs: secs(self.process.ru_stime, start.process.ru_stime),
+            thread_user_secs: secs(self.thread.ru_utime, start.thread.ru_utime),
+            thread_sys_secs: secs(self.thread.ru_stime, start.thread.ru_stime),
+            io_uring_kernel_secs: self.io_uring_ticks.saturating_sub(start.io_uring_ticks) as f64
+                / ticks_per_sec,
+            voluntary_ctx_switches: (self.process.ru_nvcsw - start.process.ru_nvcsw) as f64,
+            involuntary_ctx_switches: (self.process.ru_nivcsw - start.process.ru_nivcsw) as f64,
+            cpu_secs_per_gib: 0.0,
+        }
+    }
+}
+
+impl CpuUsage {
+    pub fn add(&mut self, other: &CpuUsage) {
+        self.user_secs += other.user_secs;
+        self.sys_secs += other.sys_secs;
+        self.thread_user_secs += other.thread_user_secs;
+        self.thread_sys_secs += other.thread_sys_secs;
+        self.io_uring_kernel_secs += other.io_uring_kernel_secs;
+        self.voluntary_ctx_switches += other.voluntary_ctx_switches;
+        self.involuntary_ctx_switches += other.involuntary_ctx_switches;
+    }
+
+    // Averages usage accumulated over `iters` passes that each moved `bytes`.
+    pub fn per_pass(mut self, iters: u32, bytes: u64) -> CpuUsage {
+        let iters = iters as f64;
+        self.user_secs /= iters;
+        self.sys_secs /= iters;
+        self.thread_user_secs /= iters;
+        self.thread_sys_secs /= iters;
+        self.io_uring_kernel_secs /= iters;
+        self.voluntary_ctx_switches /= iters;
+        self.involuntary_ctx_switches /= iters;
+        self.cpu_secs_per_gib =
+            (self.user_secs + self.sys_secs) / (bytes as f64 / 1024.0 / 1024.0 / 1024.0);
+        self
+    }
+}
+
+fn getrusage(who: libc::c_int) -> libc::rusage {
+    let mut usage = MaybeUninit::uninit();
+    // SAFETY: usage is a valid rusage to write into
+    let rc = unsafe { libc::getrusage(who, usage.as_mut_ptr()) };
+    assert_eq!(rc, 0, "getrusage: {}", std::io::Error::last_os_error());
+    // SAFETY: getrusage succeeded, so usage is initialized
+    unsafe { usage.assume_init() }
+}
+
+fn timeval_secs(tv: libc::timeval) -> f64 {
+    tv.tv_sec as f64 + tv.tv_usec as f64 / 1_000_000.0
+}
+
+// utime + stime of the io_uring SQPOLL ("iou-sqp-*") and io-wq ("iou-wrk-*") threads.
+fn io_uring_ticks() -> u64 {
+    let Ok(tasks) = std::fs::read_dir("/proc/self/task") else {
+        return 0;
+    };
+    let mut ticks = 0;
+    for task in tasks.flatten() {
+        let Ok(stat) = std::fs::read_to_string(task.path().join("stat")) else {
+            continue;
+        };
+        // the command name is in parentheses and may contain spaces
+        let (Some(comm_start), Some(comm_end)) = (stat.find('('), stat.rfind(')')) else {
+            continue;
+        };
+        if !stat[comm_start + 1..comm_end].starts_with("iou-") {
+            continue;
+        }
+        // fields after the command name start at field 3 (state); utime and stime are 14 and 15
+        let fields = stat[comm_end + 1..].split_whitespace().collect::<Vec<_>>();
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok());
+        ticks += field(14).unwrap_or(0) + field(15).unwrap_or(0);
+    }
+    ticks
+}
@@ -2,21 +2,25 @@ use std::{
     fs::OpenOptions,
     io::Write,
     os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::PathBuf,
     sync::{Arc, LazyLock, Mutex},
+    time::Instant,
 };
 
 use aiofut::{AIOBuilder, AIOManager};
 use futures::StreamExt;
+use itertools::Itertools;
 use libc::O_DIRECT;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
 use crate::{
-    IoMethod, IoSequence,
-    io_data::{access_seq, aligned_vec, buf_data},
+    IoMethod, IoOp,
+    io_data::{Workload, aligned_vec, buf_data},
+    replay::TraceOp,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct DirectAsync {
     pub block_size: u32,
     pub concurrency: u32,
@@ -37,94 +41,162 @@ fn create_aiomgr() -> Mutex<AIOManager> {
 }
 
 impl IoMethod for DirectAsync {
-    fn write_file(&self, path: &std::path::Path, file_size: u64, sequence: IoSequence) {
-        TOKIO_RUNTIME.block_on(self.write_file_inner(path, file_size, sequence))
+    fn write_file(&self, workload: &Workload) {
+        TOKIO_RUNTIME.block_on(self.write_file_inner(workload))
     }
-    fn read_file(&self, path: &std::path::Path, file_size: u64, sequence: IoSequence) {
-        TOKIO_RUNTIME.block_on(self.read_file_inner(path, file_size, sequence))
+    fn read_file(&self, workload: &Workload) {
+        TOKIO_RUNTIME.block_on(self.read_file_inner(workload))
+    }
+    fn replay(&self, workload: &Workload, trace: &[TraceOp]) {
+        TOKIO_RUNTIME.block_on(self.replay_inner(workload, trace))
     }
 }
 
 impl DirectAsync {
-    async fn write_file_inner(&self, path: &std::path::Path, file_size: u64, sequence: IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
+    async fn write_file_inner(&self, workload: &Workload<'_>) {
         let template_buf = Arc::new(buf_data(self.block_size as usize));
-        let num_pages = file_size / self.block_size as u64;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(O_DIRECT)
-            .open(path)
-            .unwrap();
-        let fd = file.as_raw_fd();
+        let mut files = open_files(workload.paths);
+        let fds = files.iter().map(|file| file.as_raw_fd()).collect_vec();
+        let queues = workload.access_queues(self.block_size);
+        let concurrency_per_queue = (self.concurrency as usize / queues.len()).max(1);
 
         let block_pool = Arc::new(Mutex::new(Vec::<Box<[u8]>>::new()));
 
-        let _results: Vec<()> = futures::stream::iter(access_seq(sequence, num_pages))
-            .map(|page_idx| {
+        futures::future::join_all(queues.into_iter().map(|queue| {
+            futures::stream::iter(queue)
+                .map(|(file_idx, page_idx)| {
+                    let template_buf = template_buf.clone();
+                    let block_pool = block_pool.clone();
+                    let fd = fds[file_idx];
+                    let issue_time = workload.issue_time();
+                    async move {
+                        wait_issue(issue_time).await;
+                        let offset = page_idx * self.block_size as u64;
+                        let buf = {
+                            let mut pool = block_pool.lock().unwrap();
+                            if let Some(buf) = pool.pop() {
+                                buf
+                            } else {
+                                let mut buf = aligned_vec(template_buf.len());
+                                buf.copy_from_slice(&template_buf);
+                                buf.into_boxed_slice()
+                            }
+                        };
+                        let (rc, buf) =
+                            { AIO_MGR.lock().unwrap().write(fd, offset, buf, None) }.await;
+                        {
+                            let mut pool = block_pool.lock().unwrap();
+                            pool.push(buf);
+                        }
+                        let written = rc.unwrap();
+                        assert_eq!(written as u32, self.block_size);
+                        workload.complete(IoOp::Write, self.block_size as u64, issue_time);
+                    }
+                })
+                .buffer_unordered(concurrency_per_queue)
+                .collect::<Vec<()>>()
+        }))
+        .await;
+
+        for file in &mut files {
+            file.flush().unwrap();
+            file.sync_all().unwrap();
+        }
+    }
+
+    async fn read_file_inner(&self, workload: &Workload<'_>) {
+        let mut files = open_files(workload.paths);
+        let fds = files.iter().map(|file| file.as_raw_fd()).collect_vec();
+        let queues = workload.access_queues(self.block_size);
+        let concurrency_per_queue = (self.concurrency as usize / queues.len()).max(1);
+
+        futures::future::join_all(queues.into_iter().map(|queue| {
+            futures::stream::iter(queue)
+                .map(|(file_idx, page_idx)| {
+                    let fd = fds[file_idx];
+                    let issue_time = workload.issue_time();
+                    async move {
+                        wait_issue(issue_time).await;
+                        let block_size = self.block_size;
+                        let offset = page_idx * self.block_size as u64;
+                        let (rc, _buf) = {
+                            AIO_MGR.lock().unwrap().read(
+                                fd,
+                                offset,
+                                aligned_vec(block_size as usize).into_boxed_slice(),
+                                None,
+                            )
+                        }
+                        .await;
+                        let written = rc.unwrap();
+                        assert_eq!(written as u32, self.block_size);
+                        workload.complete(IoOp::Read, self.block_size as u64, issue_time);
+                    }
+                })
+                .buffer_unordered(concurrency_per_queue)
+                .collect::<Vec<()>>()
+        }))
+        .await;
+
+        for file in &mut files {
+            file.flush().unwrap();
+            file.sync_all().unwrap();
+        }
+    }
+
+    async fn replay_inner(&self, workload: &Workload<'_>, trace: &[TraceOp]) {
+        let max_len = trace.iter().map(|op| op.len).max().unwrap_or(0);
+        let template_buf = Arc::new(buf_data(max_len as usize));
+        let files = open_files(&workload.paths[..1]);
+        let fd = files[0].as_raw_fd();
+
+        futures::stream::iter(trace)
+            .map(|op| {
                 let template_buf = template_buf.clone();
-                let block_pool = block_pool.clone();
+                let issue_time = workload.issue_time();
                 async move {
-                    let offset = page_idx * self.block_size as u64;
-                    let buf = {
-                        let mut pool = block_pool.lock().unwrap();
-                        if let Some(buf) = pool.pop() {
-                            buf
-                        } else {
-                            let mut buf = aligned_vec(template_buf.len());
-                            buf.copy_from_slice(&template_buf);
-                            buf.into_boxed_slice()
+                    wait_issue(issue_time).await;
+                    let len = op.len as usize;
+                    let mut buf = aligned_vec(len);
+                    let aio = {
+                        let aio_mgr = AIO_MGR.lock().unwrap();
+                        match op.op {
+                            IoOp::Read => aio_mgr.read(fd, op.offset, buf.into(), None),
+                            IoOp::Write => {
+                                buf.copy_from_slice(&template_buf[..len]);
+                                aio_mgr.write(fd, op.offset, buf.into(), None)
+                            }
                         }
                     };
-                    let (rc, buf) = { AIO_MGR.lock().unwrap().write(fd, offset, buf, None) }.await;
-                    {
-                        let mut pool = block_pool.lock().unwrap();
-                        pool.push(buf);
-                    }
-                    let written = rc.unwrap();
-                    assert_eq!(written as u32, self.block_size);
+                    let (rc, _buf) = aio.await;
+                    assert_eq!(rc.unwrap(), len);
+                    workload.complete(op.op, op.len as u64, issue_time);
                 }
             })
             .buffer_unordered(self.concurrency as usize)
-            .collect()
+            .collect::<Vec<()>>()
             .await;
 
-        file.flush().unwrap();
-        file.sync_all().unwrap();
+        files[0].sync_all().unwrap();
     }
+}
 
-    async fn read_file_inner(&self, path: &std::path::Path, file_size: u64, sequence: IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
-        let num_pages = file_size / self.block_size as u64;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(O_DIRECT)
-            .open(path)
-            .unwrap();
-        let fd = file.as_raw_fd();
-
-        let _results: Vec<()> = futures::stream::iter(access_seq(sequence, num_pages))
-            .map(|page_idx| async move {
-                let block_size = self.block_size;
-                let offset = page_idx * self.block_size as u64;
-                let (rc, _buf) = {
-                    AIO_MGR.lock().unwrap().read(
-                        fd,
-                        offset,
-                        aligned_vec(block_size as usize).into_boxed_slice(),
-                        None,
-                    )
-                }
-                .await;
-                let written = rc.unwrap();
-                assert_eq!(written as u32, self.block_size);
-            })
-            .buffer_unordered(self.concurrency as usize)
-            .collect()
-            .await;
-
-        file.flush().unwrap();
-        file.sync_all().unwrap();
+async fn wait_issue(issue_time: Option<Instant>) {
+    if let Some(issue_time) = issue_time {
+        tokio::time::sleep_until(issue_time.into()).await;
     }
 }
+
+fn open_files(paths: &[PathBuf]) -> Vec<std::fs::File> {
+    paths
+        .iter()
+        .map(|path| {
+            OpenOptions::new()
+                .write(true)
+                .read(true)
+                .custom_flags(O_DIRECT)
+                .open(path)
+                .unwrap()
+        })
+        .collect()
+}
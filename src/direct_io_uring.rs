@@ -1,8 +1,10 @@
 use std::{
+    collections::VecDeque,
     fs::OpenOptions,
     io::Write,
     os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
     sync::{LazyLock, Mutex},
+    time::Instant,
 };
 
 use io_uring::{IoUring, opcode, types};
@@ -11,14 +13,18 @@ use libc::O_DIRECT;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    IoMethod,
-    io_data::{access_seq, aligned_vec, buf_data},
+    IoMethod, IoOp,
+    io_data::{SegmentedBuf, Vectored, Workload, aligned_vec, buf_data},
+    pacing::{sleep_until, uring_wait},
+    replay::TraceOp,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct DirectUring {
     pub block_size: u32,
     pub concurrency: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vectored: Option<Vectored>,
 }
 
 static URING: LazyLock<Mutex<IoUring>> = LazyLock::new(create_uring);
@@ -28,123 +34,278 @@ fn create_uring() -> Mutex<IoUring> {
 }
 
 impl IoMethod for DirectUring {
-    fn write_file(&self, path: &std::path::Path, file_size: u64, sequence: crate::IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
+    fn write_file(&self, workload: &Workload) {
         let buf = buf_data(self.block_size as usize);
-        let num_pages = file_size / self.block_size as u64;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(O_DIRECT)
-            .open(path)
-            .unwrap();
-        let mut remaining_pages = access_seq(sequence, num_pages).collect_vec().into_iter();
+        let segmented = self
+            .vectored
+            .map(|vectored| SegmentedBuf::new(vectored, self.block_size, true));
+        let mut files = workload
+            .paths
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .custom_flags(O_DIRECT)
+                    .open(path)
+                    .unwrap()
+            })
+            .collect_vec();
+        let mut queues = workload.access_queues(self.block_size);
+        let queue_count = queues.len();
         let binding = &*URING;
         let mut uring = binding.lock().unwrap();
-        let mut pending_writes = 0;
-        for page in (&mut remaining_pages).take(self.concurrency as usize) {
-            let entry =
-                opcode::Write::new(types::Fd(file.as_raw_fd()), buf.as_ptr(), buf.len() as u32)
-                    .offset(page * self.block_size as u64)
-                    .build()
-                    .user_data(1);
-            unsafe {
-                // SAFETY: fd and buffer are valid for the duration of the operation
-                uring.submission().push(&entry).unwrap();
+        uring
+            .submitter()
+            .register_files(&files.iter().map(|file| file.as_raw_fd()).collect_vec())
+            .unwrap();
+        let mut issue_times = vec![None; self.concurrency as usize];
+        // (slot, file, page, issue time) of writes waiting for their issue time
+        let mut waiting = VecDeque::new();
+        for slot in 0..self.concurrency as usize {
+            if let Some((file_idx, page)) = queues[slot % queue_count].next() {
+                waiting.push_back((slot, file_idx, page, workload.issue_time()));
             }
-            pending_writes += 1;
         }
-        uring.submit().unwrap();
-        while pending_writes > 0 {
-            uring.submit().unwrap();
-            uring.submit_and_wait(1).unwrap();
+        let mut pending_writes = 0;
+        loop {
+            let now = Instant::now();
+            while let Some(&(slot, file_idx, page, issue_time)) = waiting.front() {
+                if issue_time.is_some_and(|issue_time| issue_time > now) {
+                    break;
+                }
+                waiting.pop_front();
+                let fd = types::Fixed(file_idx as u32);
+                let offset = page * self.block_size as u64;
+                let entry = match &segmented {
+                    Some(segmented) => opcode::Writev::new(
+                        fd,
+                        segmented.iovecs().as_ptr(),
+                        segmented.iovecs().len() as u32,
+                    )
+                    .offset(offset)
+                    .build(),
+                    None => opcode::Write::new(fd, buf.as_ptr(), buf.len() as u32)
+                        .offset(offset)
+                        .build(),
+                }
+                .user_data(slot as u64);
+                unsafe {
+                    // SAFETY: fd and buffer are valid for the duration of the operation
+                    uring.submission().push(&entry).unwrap();
+                }
+                issue_times[slot] = issue_time;
+                pending_writes += 1;
+            }
+            let next_issue_time = waiting
+                .front()
+                .and_then(|&(_, _, _, issue_time)| issue_time);
+            if pending_writes == 0 {
+                match next_issue_time {
+                    Some(next_issue_time) => {
+                        sleep_until(next_issue_time);
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            uring_wait(&uring, next_issue_time);
             uring.completion().sync();
             while let Some(entry) = { uring.completion().next() } {
                 assert_eq!(entry.result(), buf.len() as i32);
+                let slot = entry.user_data() as usize;
+                workload.complete(IoOp::Write, self.block_size as u64, issue_times[slot]);
                 pending_writes -= 1;
-                if let Some(page) = remaining_pages.next() {
-                    let entry = opcode::Write::new(
-                        types::Fd(file.as_raw_fd()),
-                        buf.as_ptr(),
-                        buf.len() as u32,
+                if let Some((file_idx, page)) = queues[slot % queue_count].next() {
+                    waiting.push_back((slot, file_idx, page, workload.issue_time()));
+                }
+            }
+        }
+        uring.submitter().unregister_files().unwrap();
+        for file in &mut files {
+            file.flush().unwrap();
+            file.sync_all().unwrap();
+        }
+    }
+
+    fn read_file(&self, workload: &Workload) {
+        let mut bufs = (0..self.concurrency)
+            .map(|_| aligned_vec(self.block_size as usize))
+            .collect_vec();
+        // one per buffer when vectored, empty otherwise
+        let segmented = match self.vectored {
+            Some(vectored) => (0..self.concurrency)
+                .map(|_| SegmentedBuf::new(vectored, self.block_size, false))
+                .collect_vec(),
+            None => Vec::new(),
+        };
+        let files = workload
+            .paths
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .custom_flags(O_DIRECT)
+                    .open(path)
+                    .unwrap()
+            })
+            .collect_vec();
+        let mut queues = workload.access_queues(self.block_size);
+        let queue_count = queues.len();
+        let binding = &*URING;
+        let mut uring = binding.lock().unwrap();
+        uring
+            .submitter()
+            .register_files(&files.iter().map(|file| file.as_raw_fd()).collect_vec())
+            .unwrap();
+        let mut issue_times = vec![None; self.concurrency as usize];
+        // (buffer, file, page, issue time) of reads waiting for their issue time
+        let mut waiting = VecDeque::new();
+        for buf_idx in 0..self.concurrency as usize {
+            if let Some((file_idx, page)) = queues[buf_idx % queue_count].next() {
+                waiting.push_back((buf_idx, file_idx, page, workload.issue_time()));
+            }
+        }
+        let mut pending_reads = 0;
+        loop {
+            let now = Instant::now();
+            while let Some(&(buf_idx, file_idx, page, issue_time)) = waiting.front() {
+                if issue_time.is_some_and(|issue_time| issue_time > now) {
+                    break;
+                }
+                waiting.pop_front();
+                let buf = &mut bufs[buf_idx];
+                let fd = types::Fixed(file_idx as u32);
+                let offset = page * self.block_size as u64;
+                let entry = match segmented.get(buf_idx) {
+                    Some(segmented) => opcode::Readv::new(
+                        fd,
+                        segmented.iovecs().as_ptr(),
+                        segmented.iovecs().len() as u32,
                     )
-                    .offset(page * self.block_size as u64)
-                    .build()
-                    .user_data(1);
-                    unsafe {
-                        // SAFETY: fd and buffer are valid for the duration of the operation
-                        uring.submission().push(&entry).unwrap();
+                    .offset(offset)
+                    .build(),
+                    None => opcode::Read::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+                        .offset(offset)
+                        .build(),
+                }
+                .user_data(buf_idx as u64);
+                unsafe {
+                    // SAFETY: fd and buffer are valid for the duration of the operation
+                    uring.submission().push(&entry).unwrap();
+                }
+                issue_times[buf_idx] = issue_time;
+                pending_reads += 1;
+            }
+            let next_issue_time = waiting
+                .front()
+                .and_then(|&(_, _, _, issue_time)| issue_time);
+            if pending_reads == 0 {
+                match next_issue_time {
+                    Some(next_issue_time) => {
+                        sleep_until(next_issue_time);
+                        continue;
                     }
-                    pending_writes += 1;
-                    uring.submit().unwrap();
+                    None => break,
+                }
+            }
+            uring_wait(&uring, next_issue_time);
+            uring.completion().sync();
+            while let Some(entry) = { uring.completion().next() } {
+                assert_eq!(entry.result(), self.block_size as i32);
+                let buf_idx = entry.user_data() as usize;
+                workload.complete(IoOp::Read, self.block_size as u64, issue_times[buf_idx]);
+                pending_reads -= 1;
+                if let Some((file_idx, page)) = queues[buf_idx % queue_count].next() {
+                    waiting.push_back((buf_idx, file_idx, page, workload.issue_time()));
                 }
             }
         }
-        file.flush().unwrap();
-        file.sync_all().unwrap();
+        uring.submitter().unregister_files().unwrap();
     }
 
-    fn read_file(&self, path: &std::path::Path, file_size: u64, sequence: crate::IoSequence) {
-        assert_eq!(file_size % self.block_size as u64, 0);
+    fn replay(&self, workload: &Workload, trace: &[TraceOp]) {
+        let max_len = trace.iter().map(|op| op.len).max().unwrap_or(0);
+        let template_buf = buf_data(max_len as usize);
         let mut bufs = (0..self.concurrency)
-            .map(|_| aligned_vec(self.block_size as usize))
+            .map(|_| {
+                let mut buf = aligned_vec(max_len as usize);
+                buf.copy_from_slice(&template_buf);
+                buf
+            })
             .collect_vec();
-        let mut available_buf_idx = (0..self.concurrency as usize).collect_vec();
-        let num_pages = file_size / self.block_size as u64;
-        let file = OpenOptions::new()
+        let mut file = OpenOptions::new()
             .write(true)
             .read(true)
             .custom_flags(O_DIRECT)
-            .open(path)
+            .open(&workload.paths[0])
             .unwrap();
-        let mut remaining_pages = access_seq(sequence, num_pages).collect_vec().into_iter();
+        let mut trace = trace.iter();
         let binding = &*URING;
         let mut uring = binding.lock().unwrap();
-        let mut pending_reads = 0;
-        for page in (&mut remaining_pages).take(self.concurrency as usize) {
-            let buf_idx = available_buf_idx.pop().unwrap();
-            let buf = &mut bufs[buf_idx];
-            let entry = opcode::Read::new(
-                types::Fd(file.as_raw_fd()),
-                buf.as_mut_ptr(),
-                buf.len() as u32,
-            )
-            .offset(page * self.block_size as u64)
-            .build()
-            .user_data(buf_idx as u64);
-            unsafe {
-                // SAFETY: fd and buffer are valid for the duration of the operation
-                uring.submission().push(&entry).unwrap();
+        uring
+            .submitter()
+            .register_files(&[file.as_raw_fd()])
+            .unwrap();
+        let mut in_flight = vec![None; self.concurrency as usize];
+        // (slot, operation, issue time) of operations waiting for their issue time
+        let mut waiting = VecDeque::new();
+        for slot in 0..self.concurrency as usize {
+            if let Some(op) = trace.next() {
+                waiting.push_back((slot, op, workload.issue_time()));
             }
-            pending_reads += 1;
         }
-        uring.submit().unwrap();
-        while pending_reads > 0 {
-            uring.submit().unwrap();
-            uring.submit_and_wait(1).unwrap();
+        let mut pending_ops = 0;
+        loop {
+            let now = Instant::now();
+            while let Some(&(slot, op, issue_time)) = waiting.front() {
+                if issue_time.is_some_and(|issue_time| issue_time > now) {
+                    break;
+                }
+                waiting.pop_front();
+                let buf = &mut bufs[slot];
+                let entry = match op.op {
+                    IoOp::Read => opcode::Read::new(types::Fixed(0), buf.as_mut_ptr(), op.len)
+                        .offset(op.offset)
+                        .build(),
+                    IoOp::Write => opcode::Write::new(types::Fixed(0), buf.as_ptr(), op.len)
+                        .offset(op.offset)
+                        .build(),
+                }
+                .user_data(slot as u64);
+                unsafe {
+                    // SAFETY: fd and buffer are valid for the duration of the operation
+                    uring.submission().push(&entry).unwrap();
+                }
+                in_flight[slot] = Some((op, issue_time));
+                pending_ops += 1;
+            }
+            let next_issue_time = waiting.front().and_then(|&(_, _, issue_time)| issue_time);
+            if pending_ops == 0 {
+                match next_issue_time {
+                    Some(next_issue_time) => {
+                        sleep_until(next_issue_time);
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            uring_wait(&uring, next_issue_time);
             uring.completion().sync();
             while let Some(entry) = { uring.completion().next() } {
-                assert_eq!(entry.result(), self.block_size as i32);
-                let buf_idx = entry.user_data() as usize;
-                let buf = &mut bufs[buf_idx];
-                pending_reads -= 1;
-                if let Some(page) = remaining_pages.next() {
-                    let entry = opcode::Read::new(
-                        types::Fd(file.as_raw_fd()),
-                        buf.as_mut_ptr(),
-                        buf.len() as u32,
-                    )
-                    .offset(page * self.block_size as u64)
-                    .build()
-                    .user_data(buf_idx as u64);
-                    unsafe {
-                        // SAFETY: fd and buffer are valid for the duration of the operation
-                        uring.submission().push(&entry).unwrap();
-                    }
-                    pending_reads += 1;
-                    uring.submit().unwrap();
+                let slot = entry.user_data() as usize;
+                let (op, issue_time) = in_flight[slot].take().unwrap();
+                assert_eq!(entry.result(), op.len as i32);
+                workload.complete(op.op, op.len as u64, issue_time);
+                pending_ops -= 1;
+                if let Some(op) = trace.next() {
+                    waiting.push_back((slot, op, workload.issue_time()));
                 }
             }
         }
+        uring.submitter().unregister_files().unwrap();
+        file.flush().unwrap();
+        file.sync_all().unwrap();
     }
 }
@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+// Device-level counters of the device backing the test files, averaged per pass like `CpuUsage`.
+// Requests are counted after merging, so merges and the average request size show what the
+// block layer actually sent to the device.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DiskStats {
+    pub device: String,
+    pub reads: f64,
+    pub reads_merged: f64,
+    pub read_sectors: f64,
+    pub writes: f64,
+    pub writes_merged: f64,
+    pub write_sectors: f64,
+    pub io_ms: f64,
+    pub queue_ms: f64,
+    pub avg_read_request_bytes: f64,
+    pub avg_write_request_bytes: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockDevice {
+    major: u32,
+    minor: u32,
+}
+
+// Fields of /sys/block/<dev>/stat, which are the same as in /proc/diskstats after the name.
+pub struct DiskSnapshot {
+    device: BlockDevice,
+    counters: [u64; 11],
+}
+
+// The device holding `path`, or the device itself for block device targets. Files that don't
+// exist yet are resolved through their directory. Returns None for filesystems without a backing
+// block device such as tmpfs or overlayfs.
+pub fn backing_device(path: &Path) -> Option<BlockDevice> {
+    let metadata = std::fs::metadata(path)
+        .or_else(|_| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => std::fs::metadata(parent),
+            _ => std::fs::metadata("."),
+        })
+        .ok()?;
+    let dev = if metadata.file_type().is_block_device() {
+        metadata.rdev()
+    } else {
+        metadata.dev()
+    };
+    let device = BlockDevice {
+        major: libc::major(dev),
+        minor: libc::minor(dev),
+    };
+    read_counters(device).map(|_| device)
+}
+
+impl BlockDevice {
+    pub fn sys_path(&self) -> PathBuf {
+        PathBuf::from(format!("/sys/dev/block/{self}"))
+    }
+
+    // The kernel name, e.g. nvme0n1p2
+    pub fn name(&self) -> String {
+        std::fs::canonicalize(self.sys_path())
+            .ok()
+            .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| self.to_string())
+    }
+}
+
+impl fmt::Display for BlockDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)
+    }
+}
+
+impl DiskSnapshot {
+    pub fn now(device: Option<BlockDevice>) -> Option<DiskSnapshot> {
+        let device = device?;
+        Some(DiskSnapshot {
+            device,
+            counters: read_counters(device)?,
+        })
+    }
+
+    pub fn stats_since(&self, start: &DiskSnapshot) -> DiskStats {
+        let delta = |field: usize| self.counters[field].wrapping_sub(start.counters[field]) as f64;
+        DiskStats {
+            device: self.device.name(),
+            reads: delta(0),
+            reads_merged: delta(1),
+            read_sectors: delta(2),
+            writes: delta(4),
+            writes_merged: delta(5),
+            write_sectors: delta(6),
+            io_ms: delta(9),
+            queue_ms: delta(10),
+            ..Default::default()
+        }
+    }
+}
+
+impl DiskStats {
+    pub fn add(&mut self, other: &DiskStats) {
+        self.device.clone_from(&other.device);
+        self.reads += other.reads;
+        self.reads_merged += other.reads_merged;
+        self.read_sectors += other.read_sectors;
+        self.writes += other.writes;
+        self.writes_merged += other.writes_merged;
+        self.write_sectors += other.write_sectors;
+        self.io_ms += other.io_ms;
+        self.queue_ms += other.queue_ms;
+    }
+
+    pub fn per_pass(mut self, iters: u32) -> DiskStats {
+        let iters = iters as f64;
+        self.reads /= iters;
+        self.reads_merged /= iters;
+        self.read_sectors /= iters;
+        self.writes /= iters;
+        self.writes_merged /= iters;
+        self.write_sectors /= iters;
+        self.io_ms /= iters;
+        self.queue_ms /= iters;
+        let avg_request_bytes = |sectors: f64, requests: f64| {
+            if requests == 0.0 {
+                0.0
+            } else {
+                sectors * 512.0 / requests
+            }
+        };
+        self.avg_read_request_bytes = avg_request_bytes(self.read_sectors, self.reads);
+        self.avg_write_request_bytes = avg_request_bytes(self.write_sectors, self.writes);
+        self
+    }
+}
+
+// /sys/dev/block/MAJ:MIN links to the disk or partition directory; /proc/diskstats is the fallback
+// for kernels without it.
+fn read_counters(device: BlockDevice) -> Option<[u64; 11]> {
+    if let Ok(stat) = std::fs::read_to_string(device.sys_path().join("stat")) {
+        return parse_counters(stat.split_whitespace());
+    }
+    let diskstats = std::fs::read_to_string("/proc/diskstats").ok()?;
+    diskstats.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let major = fields.next()?.parse::<u32>().ok()?;
+        let minor = fields.next()?.parse::<u32>().ok()?;
+        let _name = fields.next()?;
+        if (major, minor) != (device.major, device.minor) {
+            return None;
+        }
+        parse_counters(fields)
+    })
+}
+
+fn parse_counters<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<[u64; 11]> {
+    let mut counters = [0; 11];
+    for counter in &mut counters {
+        *counter = fields.next()?.parse().ok()?;
+    }
+    Some(counters)
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    IoOp, IoSequence, Report, ReportItem,
+    bench_settings::{BenchSettings, FileLayout, IoMethodSettings, read_bench_settings},
+    buffered_io::Buffered,
+    buffered_io_uring::BufferedUring,
+    direct_async_io::DirectAsync,
+    direct_io::Direct,
+    direct_io_uring::DirectUring,
+    report::read_report,
+    rw_flags::RwFlag,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExportFioCommand {
+    #[clap(long, value_parser, default_value = "benchmark.json")]
+    settings_file: PathBuf,
+    #[clap(long, value_parser, default_value = "target/test_file")]
+    test_file: PathBuf,
+    /// Write the job file here instead of stdout
+    #[clap(long, value_parser)]
+    job_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ImportFioCommand {
+    /// Output of `fio --output-format=json`
+    #[clap(long, value_parser)]
+    fio_json_file: PathBuf,
+    /// Target label of the imported items
+    #[clap(long, default_value = "fio")]
+    label: String,
+    /// Appended to if it exists
+    #[clap(long, value_parser, default_value = "target/report.json")]
+    report_file: PathBuf,
+}
+
+// Only the single file layout is exported, every job runs against the same file one after another.
+pub fn run_export_fio(command: &ExportFioCommand) {
+    let settings = read_bench_settings(&command.settings_file);
+    let jobs = job_file(&settings, &command.test_file);
+    match &command.job_file {
+        Some(job_file) => std::fs::write(job_file, jobs).unwrap(),
+        None => print!("{jobs}"),
+    }
+}
+
+fn job_file(settings: &BenchSettings, test_file: &Path) -> String {
+    let mut jobs = String::new();
+    writeln!(jobs, "[global]").unwrap();
+    writeln!(jobs, "filename={}", test_file.display()).unwrap();
+    writeln!(jobs, "size={}", settings.file_size).unwrap();
+    writeln!(jobs, "fsync_on_close=1").unwrap();
+    writeln!(jobs, "stonewall").unwrap();
+    for m in &settings.methods {
+        for sequence in [IoSequence::Sequential, IoSequence::Random] {
+            for op in [IoOp::Write, IoOp::Read] {
+                writeln!(jobs).unwrap();
+                write_job(&mut jobs, m, sequence, op);
+            }
+        }
+    }
+    jobs
+}
+
+fn write_job(jobs: &mut String, m: &IoMethodSettings, sequence: IoSequence, op: IoOp) {
+    let sync_engine = if m.rw_flags().is_empty() {
+        "psync"
+    } else {
+        "pvsync2"
+    };
+    let (ioengine, direct) = match m {
+        IoMethodSettings::Buffered(_) => (sync_engine, 0),
+        IoMethodSettings::BufferedUring(_) => ("io_uring", 0),
+        IoMethodSettings::Direct(_) => (sync_engine, 1),
+        IoMethodSettings::DirectAsync(_) => ("libaio", 1),
+        IoMethodSettings::DirectUring(_) => ("io_uring", 1),
+    };
+    let rw = match (sequence, op) {
+        (IoSequence::Sequential, IoOp::Write) => "write",
+        (IoSequence::Sequential, IoOp::Read) => "read",
+        (IoSequence::Random, IoOp::Write) => "randwrite",
+        (IoSequence::Random, IoOp::Read) => "randread",
+    };
+    writeln!(
+        jobs,
+        "[{name}-bs{bs}-qd{qd}-{rw}]",
+        name = m.name(),
+        bs = m.block_size(),
+        qd = m.concurrency(),
+    )
+    .unwrap();
+    if m.vectored().is_some() {
+        writeln!(
+            jobs,
+            "# fio can't split blocks into segments, this job uses one buffer"
+        )
+        .unwrap();
+    }
+    writeln!(jobs, "ioengine={ioengine}").unwrap();
+    writeln!(jobs, "direct={direct}").unwrap();
+    writeln!(jobs, "bs={}", m.block_size()).unwrap();
+    writeln!(jobs, "iodepth={}", m.concurrency()).unwrap();
+    writeln!(jobs, "rw={rw}").unwrap();
+    if ioengine == "io_uring" {
+        writeln!(jobs, "registerfiles=1").unwrap();
+    }
+    for flag in m.rw_flags() {
+        let option = match flag {
+            RwFlag::Nowait => "nowait=1",
+            RwFlag::Hipri => "hipri=1",
+            // per-file rather than per-call, but the same durability for every write
+            RwFlag::Dsync => "sync=dsync",
+            RwFlag::Uncached => "uncached=1",
+        };
+        writeln!(jobs, "{option}").unwrap();
+    }
+}
+
+#[derive(Deserialize)]
+struct FioOutput {
+    #[serde(rename = "global options", default)]
+    global_options: BTreeMap<String, String>,
+    jobs: Vec<FioJob>,
+}
+
+#[derive(Deserialize)]
+struct FioJob {
+    jobname: String,
+    #[serde(rename = "job options", default)]
+    job_options: BTreeMap<String, String>,
+    read: FioJobStats,
+    write: FioJobStats,
+}
+
+#[derive(Deserialize)]
+struct FioJobStats {
+    bw_bytes: u64,
+}
+
+pub fn run_import_fio(command: &ImportFioCommand) {
+    let output: FioOutput =
+        serde_json::from_slice(&std::fs::read(&command.fio_json_file).unwrap()).unwrap();
+    let items = fio_items(&output, &command.label);
+    println!(
+        "imported {count} items from {path}",
+        count = items.len(),
+        path = command.fio_json_file.display()
+    );
+
+    let mut report = if command.report_file.exists() {
+        read_report(&command.report_file)
+    } else {
+        Report::default()
+    };
+    report.items.extend(items);
+    std::fs::write(
+        &command.report_file,
+        serde_json::to_string(&report).unwrap(),
+    )
+    .unwrap();
+}
+
+// Jobs with the same method and sequence are merged into one item, so the write and read jobs
+// of an exported job file end up next to each other like in our own reports.
+fn fio_items(output: &FioOutput, label: &str) -> Vec<ReportItem> {
+    let mut items: Vec<ReportItem> = Vec::new();
+    for job in &output.jobs {
+        let mut options = output.global_options.clone();
+        options.extend(job.job_options.clone());
+        let (sequence, op) = match option(&options, "rw").unwrap_or("read") {
+            "read" => (IoSequence::Sequential, IoOp::Read),
+            "write" => (IoSequence::Sequential, IoOp::Write),
+            "randread" => (IoSequence::Random, IoOp::Read),
+            "randwrite" => (IoSequence::Random, IoOp::Write),
+            rw => panic!("{jobname}: unsupported rw={rw}", jobname = job.jobname),
+        };
+        let method = fio_method(&job.jobname, &options);
+        let item = match items
+            .iter_mut()
+            .find(|item| item.method == method && item.sequence == sequence)
+        {
+            Some(item) => item,
+            None => {
+                let mut item = ReportItem::new(method, sequence, None, FileLayout::Single);
+                item.target = Some(label.to_string());
+                items.push(item);
+                items.last_mut().unwrap()
+            }
+        };
+        match op {
+            IoOp::Read => item.read_tput_mbps = job.read.bw_bytes as f64 / 1024.0 / 1024.0,
+            IoOp::Write => item.write_tput_mbps = job.write.bw_bytes as f64 / 1024.0 / 1024.0,
+        }
+    }
+    items
+}
+
+fn option<'a>(options: &'a BTreeMap<String, String>, name: &str) -> Option<&'a str> {
+    options.get(name).map(|value| value.as_str())
+}
+
+fn fio_method(jobname: &str, options: &BTreeMap<String, String>) -> IoMethodSettings {
+    let block_size = fio_size(option(options, "bs").unwrap_or("4k"));
+    let concurrency = option(options, "iodepth").map_or(1, |iodepth| iodepth.parse().unwrap());
+    let direct = option(options, "direct").is_some_and(|direct| direct != "0");
+    let enabled = |name| option(options, name).is_some_and(|value| value != "0");
+    let rw_flags = [
+        (enabled("nowait"), RwFlag::Nowait),
+        (enabled("hipri"), RwFlag::Hipri),
+        (option(options, "sync") == Some("dsync"), RwFlag::Dsync),
+        (enabled("uncached"), RwFlag::Uncached),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, flag)| enabled.then_some(flag))
+    .collect::<Vec<_>>();
+    match (option(options, "ioengine").unwrap_or("psync"), direct) {
+        ("psync" | "sync" | "pvsync" | "pvsync2", false) => IoMethodSettings::Buffered(Buffered {
+            block_size,
+            vectored: None,
+            rw_flags,
+        }),
+        ("psync" | "sync" | "pvsync" | "pvsync2", true) => IoMethodSettings::Direct(Direct {
+            block_size,
+            vectored: None,
+            rw_flags,
+        }),
+        ("libaio", true) => IoMethodSettings::DirectAsync(DirectAsync {
+            block_size,
+            concurrency,
+        }),
+        ("io_uring", false) => IoMethodSettings::BufferedUring(BufferedUring {
+            block_size,
+            concurrency,
+            vectored: None,
+        }),
+        ("io_uring", true) => IoMethodSettings::DirectUring(DirectUring {
+            block_size,
+            concurrency,
+            vectored: None,
+        }),
+        (ioengine, direct) => {
+            panic!("{jobname}: no matching method for ioengine={ioengine} direct={direct}")
+        }
+    }
+}
+
+// fio sizes are plain bytes or take a binary k/m/g suffix, optionally followed by "b" or "ib".
+fn fio_size(size: &str) -> u32 {
+    let lower = size.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match lower[digits.len()..]
+        .trim_end_matches("ib")
+        .trim_end_matches('b')
+    {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        suffix => panic!("unsupported size suffix {suffix:?} in {size:?}"),
+    };
+    digits.parse::<u32>().unwrap() * multiplier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_file_rendering() {
+        let settings: BenchSettings = serde_json::from_str(
+            r#"{
+                "file_size": 1048576,
+                "methods": [
+                    { "type": "buffered", "block_size": 4096, "rw_flags": ["nowait", "dsync"] },
+                    { "type": "direct_uring", "block_size": 65536, "concurrency": 32,
+                      "vectored": { "iovecs": 4, "segment_size": 16384 } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let jobs = job_file(&settings, Path::new("/mnt/test_file"));
+        assert!(jobs.starts_with(
+            "[global]\nfilename=/mnt/test_file\nsize=1048576\nfsync_on_close=1\nstonewall\n\n"
+        ));
+        // every method is run sequentially and randomly, writing before reading
+        let names = jobs
+            .lines()
+            .filter(|line| line.starts_with('[') && *line != "[global]")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "[buffered-bs4096-qd1-write]",
+                "[buffered-bs4096-qd1-read]",
+                "[buffered-bs4096-qd1-randwrite]",
+                "[buffered-bs4096-qd1-randread]",
+                "[direct_uring-bs65536-qd32-write]",
+                "[direct_uring-bs65536-qd32-read]",
+                "[direct_uring-bs65536-qd32-randwrite]",
+                "[direct_uring-bs65536-qd32-randread]",
+            ]
+        );
+        assert!(jobs.contains(
+            "[buffered-bs4096-qd1-randread]\nioengine=pvsync2\ndirect=0\nbs=4096\niodepth=1\n\
+             rw=randread\nnowait=1\nsync=dsync\n"
+        ));
+        assert!(jobs.contains(
+            "[direct_uring-bs65536-qd32-write]\n\
+             # fio can't split blocks into segments, this job uses one buffer\n\
+             ioengine=io_uring\ndirect=1\nbs=65536\niodepth=32\nrw=write\nregisterfiles=1\n"
+        ));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(fio_size("4096"), 4096);
+        assert_eq!(fio_size("4k"), 4096);
+        assert_eq!(fio_size("64K"), 65536);
+        assert_eq!(fio_size("64kb"), 65536);
+        assert_eq!(fio_size("64KiB"), 65536);
+        assert_eq!(fio_size("1m"), 1024 * 1024);
+        assert_eq!(fio_size("1g"), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported size suffix \"t\"")]
+    fn unsupported_size() {
+        fio_size("1t");
+    }
+
+    #[test]
+    fn import() {
+        let output: FioOutput = serde_json::from_str(
+            r#"{
+                "global options": { "filename": "/dev/nvme0n1", "direct": "1" },
+                "jobs": [
+                    {
+                        "jobname": "w",
+                        "job options": { "ioengine": "io_uring", "bs": "64k", "iodepth": "32", "rw": "randwrite" },
+                        "read": { "bw_bytes": 0 },
+                        "write": { "bw_bytes": 1048576000 }
+                    },
+                    {
+                        "jobname": "r",
+                        "job options": { "ioengine": "io_uring", "bs": "65536", "iodepth": "32", "rw": "randread" },
+                        "read": { "bw_bytes": 2097152000 },
+                        "write": { "bw_bytes": 0 }
+                    },
+                    {
+                        "jobname": "psync",
+                        "job options": { "ioengine": "pvsync2", "direct": "0", "bs": "4k", "rw": "read",
+                                         "nowait": "1", "hipri": "0", "sync": "dsync" },
+                        "read": { "bw_bytes": 104857600 },
+                        "write": { "bw_bytes": 0 }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let items = fio_items(&output, "fio");
+        assert_eq!(items.len(), 2);
+        // the write and read jobs of the same method and sequence end up in one item
+        assert_eq!(
+            items[0].method,
+            IoMethodSettings::DirectUring(DirectUring {
+                block_size: 65536,
+                concurrency: 32,
+                vectored: None,
+            })
+        );
+        assert_eq!(items[0].sequence, IoSequence::Random);
+        assert_eq!(items[0].target.as_deref(), Some("fio"));
+        assert_eq!(items[0].write_tput_mbps, 1000.0);
+        assert_eq!(items[0].read_tput_mbps, 2000.0);
+        assert_eq!(items[0].preallocate, None);
+        // job options override global ones
+        assert_eq!(
+            items[1].method,
+            IoMethodSettings::Buffered(Buffered {
+                block_size: 4096,
+                vectored: None,
+                rw_flags: vec![RwFlag::Nowait, RwFlag::Dsync],
+            })
+        );
+        assert_eq!(items[1].sequence, IoSequence::Sequential);
+        assert_eq!(items[1].write_tput_mbps, 0.0);
+        assert_eq!(items[1].read_tput_mbps, 100.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "r: unsupported rw=randrw")]
+    fn import_mixed_job() {
+        let output: FioOutput = serde_json::from_str(
+            r#"{ "jobs": [ { "jobname": "r", "job options": { "rw": "randrw" },
+                 "read": { "bw_bytes": 0 }, "write": { "bw_bytes": 0 } } ] }"#,
+        )
+        .unwrap();
+        fio_items(&output, "fio");
+    }
+}
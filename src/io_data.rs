@@ -1,11 +1,87 @@
 use std::{
     alloc::{Layout, alloc},
+    fs::File,
+    path::PathBuf,
     ptr::write_bytes,
+    time::Instant,
 };
 
 use rand::{RngCore, rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
 
-use crate::IoSequence;
+use crate::{
+    IoOp, IoSequence,
+    bench_settings::FileLayout,
+    pacing::{Pacer, sleep_until},
+    page_cache::{Fadvise, fadvise},
+    rw_flags::{RwFlag, transfer},
+    sampler::Sampler,
+};
+
+pub struct Workload<'a> {
+    pub paths: &'a [PathBuf],
+    pub file_size: u64,
+    pub sequence: IoSequence,
+    pub layout: FileLayout,
+    pub pacer: Option<&'a Pacer>,
+    // posix_fadvise advice for the buffered backends, see `ReadaheadOverride`
+    pub fadvise: Option<Fadvise>,
+    pub sampler: Option<&'a Sampler>,
+}
+
+impl Workload<'_> {
+    pub fn access_queues(&self, block_size: u32) -> Vec<std::vec::IntoIter<(usize, u64)>> {
+        assert_eq!(self.file_size % block_size as u64, 0);
+        let num_pages = self.file_size / block_size as u64;
+        let queues = access_queues(self.sequence, self.layout, self.paths.len(), num_pages);
+        let Some(pacer) = self.pacer else {
+            return queues;
+        };
+        // an open-loop run can last longer than one pass over the files, the pages repeat then
+        let ops_per_queue = pacer.op_limit().div_ceil(queues.len() as u64) as usize;
+        queues
+            .into_iter()
+            .map(|queue| {
+                queue
+                    .cycle()
+                    .take(ops_per_queue)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+            })
+            .collect()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.file_size * self.paths.len() as u64
+    }
+
+    pub fn advise(&self, file: &File) {
+        if let Some(advice) = self.fadvise {
+            fadvise(file, advice);
+        }
+    }
+
+    pub fn issue_time(&self) -> Option<Instant> {
+        self.pacer.map(|pacer| pacer.next_issue_time())
+    }
+
+    pub fn wait_issue(&self) -> Option<Instant> {
+        let issue_time = self.issue_time();
+        if let Some(issue_time) = issue_time {
+            sleep_until(issue_time);
+        }
+        issue_time
+    }
+
+    pub fn complete(&self, op: IoOp, bytes: u64, issue_time: Option<Instant>) {
+        if let Some(sampler) = self.sampler {
+            sampler.record(bytes);
+        }
+        if let (Some(pacer), Some(issue_time)) = (self.pacer, issue_time) {
+            pacer.record(op, issue_time);
+        }
+    }
+}
 
 pub fn access_seq(sequence: IoSequence, n: u64) -> impl Iterator<Item = u64> {
     let mut result: Vec<_> = (0..n).collect();
@@ -16,6 +92,52 @@ pub fn access_seq(sequence: IoSequence, n: u64) -> impl Iterator<Item = u64> {
     result.into_iter()
 }
 
+// Returns (file index, page index) pairs split into queues. Worker `i` takes its pages from
+// queue `i % queues.len()`, so file_per_worker gets one queue per file and other layouts share
+// a single queue.
+pub fn access_queues(
+    sequence: IoSequence,
+    layout: FileLayout,
+    file_count: usize,
+    pages_per_file: u64,
+) -> Vec<std::vec::IntoIter<(usize, u64)>> {
+    match layout {
+        FileLayout::Single => {
+            assert_eq!(file_count, 1);
+            vec![
+                access_seq(sequence, pages_per_file)
+                    .map(|page| (0, page))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ]
+        }
+        FileLayout::FilePerWorker => (0..file_count)
+            .map(|file_idx| {
+                access_seq(sequence, pages_per_file)
+                    .map(|page| (file_idx, page))
+                    .collect::<Vec<_>>()
+                    .into_iter()
+            })
+            .collect(),
+        FileLayout::RandomFile { .. } => {
+            let mut file_pages = (0..file_count)
+                .map(|_| access_seq(sequence, pages_per_file))
+                .collect::<Vec<_>>();
+            let mut file_order = (0..file_count)
+                .flat_map(|file_idx| std::iter::repeat_n(file_idx, pages_per_file as usize))
+                .collect::<Vec<_>>();
+            file_order.shuffle(&mut rng());
+            vec![
+                file_order
+                    .into_iter()
+                    .map(|file_idx| (file_idx, file_pages[file_idx].next().unwrap()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ]
+        }
+    }
+}
+
 pub fn aligned_vec(buf_size: usize) -> Vec<u8> {
     let layout = Layout::from_size_align(buf_size, 4096).unwrap();
     // SAFETY: layout is correct
@@ -33,3 +155,85 @@ pub fn buf_data(buf_size: usize) -> Vec<u8> {
     rng().fill_bytes(&mut buf);
     buf
 }
+
+// Scatter/gather I/O: each block is split into `iovecs` separately allocated segments of
+// `segment_size` bytes, which add up to the block size, and transferred with one vectored call.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Vectored {
+    pub iovecs: u32,
+    pub segment_size: u32,
+}
+
+impl Vectored {
+    pub fn contiguous(block_size: u32) -> Vectored {
+        Vectored {
+            iovecs: 1,
+            segment_size: block_size,
+        }
+    }
+}
+
+// Buffers for the vectored or flagged calls of the synchronous backends, None for plain
+// pread/pwrite.
+pub fn segmented_buf(
+    vectored: Option<Vectored>,
+    rw_flags: &[RwFlag],
+    block_size: u32,
+    data: bool,
+) -> Option<SegmentedBuf> {
+    if vectored.is_none() && rw_flags.is_empty() {
+        return None;
+    }
+    Some(SegmentedBuf::new(
+        vectored.unwrap_or(Vectored::contiguous(block_size)),
+        block_size,
+        data,
+    ))
+}
+
+// The segments of one block and the iovecs pointing at them.
+pub struct SegmentedBuf {
+    _segments: Vec<Vec<u8>>,
+    iovecs: Vec<libc::iovec>,
+}
+
+impl SegmentedBuf {
+    // With random data for writes, zeroed for reads. The segments add up to the block size, see
+    // `IoMethodSettings::validate`.
+    pub fn new(vectored: Vectored, block_size: u32, data: bool) -> SegmentedBuf {
+        debug_assert_eq!(vectored.iovecs * vectored.segment_size, block_size);
+        let mut segments = (0..vectored.iovecs)
+            .map(|_| {
+                if data {
+                    buf_data(vectored.segment_size as usize)
+                } else {
+                    aligned_vec(vectored.segment_size as usize)
+                }
+            })
+            .collect::<Vec<_>>();
+        let iovecs = segments
+            .iter_mut()
+            .map(|segment| libc::iovec {
+                iov_base: segment.as_mut_ptr().cast(),
+                iov_len: segment.len(),
+            })
+            .collect();
+        SegmentedBuf {
+            _segments: segments,
+            iovecs,
+        }
+    }
+
+    pub fn iovecs(&self) -> &[libc::iovec] {
+        &self.iovecs
+    }
+
+    pub fn write_at(&self, file: &File, offset: u64, rw_flags: &[RwFlag]) {
+        transfer(file, IoOp::Write, &self.iovecs, offset, rw_flags);
+    }
+
+    // Takes `&mut self` as the segments are written through the iovecs.
+    pub fn read_at(&mut self, file: &File, offset: u64, rw_flags: &[RwFlag]) {
+        transfer(file, IoOp::Read, &self.iovecs, offset, rw_flags);
+    }
+}
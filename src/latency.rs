@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_us: f64,
+    pub p50_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+impl LatencySummary {
+    pub fn from_samples(samples: &mut [Duration]) -> LatencySummary {
+        samples.sort_unstable();
+        let micros = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+        let percentile = |p: f64| {
+            if samples.is_empty() {
+                return 0.0;
+            }
+            let idx = ((samples.len() - 1) as f64 * p).round() as usize;
+            micros(samples[idx])
+        };
+        let total: Duration = samples.iter().sum();
+        LatencySummary {
+            count: samples.len() as u64,
+            mean_us: if samples.is_empty() {
+                0.0
+            } else {
+                micros(total) / samples.len() as f64
+            },
+            p50_us: percentile(0.5),
+            p99_us: percentile(0.99),
+            max_us: samples.last().copied().map(micros).unwrap_or(0.0),
+        }
+    }
+}
@@ -1,26 +1,66 @@
-use std::path::Path;
-
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 
-use crate::{bench_settings::IoMethodSettings, report::ReportCommand, run_benchmark::RunCommand};
+use crate::{
+    assertions::CheckCommand,
+    bench_settings::{FileLayout, IoMethodSettings, Preallocate},
+    compare::CompareCommand,
+    cpu_usage::CpuUsage,
+    disk_stats::DiskStats,
+    fio::{ExportFioCommand, ImportFioCommand},
+    io_data::Workload,
+    latency::LatencySummary,
+    pacing::OpenLoopPoint,
+    page_cache::PageCacheStats,
+    replay::{ReplayReportItem, TraceOp},
+    report::ReportCommand,
+    run_benchmark::RunCommand,
+    run_environment::RunEnvironment,
+    sampler::ThroughputSample,
+    small_files::SmallFilesReportItem,
+    steady_state::SteadyStateResult,
+};
 
+mod assertions;
 mod bench_settings;
+mod block_device;
 mod buffered_io;
 mod buffered_io_uring;
+mod charts;
+mod compare;
+mod cpu_usage;
 mod direct_async_io;
 mod direct_io;
 mod direct_io_uring;
+mod disk_stats;
+mod fio;
 mod io_data;
-
+mod latency;
+mod openmetrics;
+mod pacing;
+mod page_cache;
+mod pivot;
+mod replay;
 mod report;
+mod report_index;
+mod report_table;
 mod run_benchmark;
+mod run_environment;
+mod rw_flags;
+mod sampler;
+mod small_files;
+mod steady_state;
+mod templates;
 
 fn main() {
     let args = Args::parse();
     match &args.command {
         Command::Run(run_command) => run_benchmark::run_benchmark(run_command),
         Command::Report(report_command) => report::run_report(report_command),
+        Command::ExportFio(export_command) => fio::run_export_fio(export_command),
+        Command::ImportFio(import_command) => fio::run_import_fio(import_command),
+        Command::Compare(compare_command) => compare::run_compare(compare_command),
+        Command::Check(check_command) => assertions::run_check(check_command),
     }
 }
 
@@ -34,6 +74,10 @@ struct Args {
 enum Command {
     Run(RunCommand),
     Report(ReportCommand),
+    ExportFio(ExportFioCommand),
+    ImportFio(ImportFioCommand),
+    Compare(CompareCommand),
+    Check(CheckCommand),
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -42,15 +86,139 @@ pub enum IoSequence {
     Random,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IoOp {
+    Read,
+    Write,
+}
+
 pub trait IoMethod {
-    fn write_file(&self, path: &Path, file_size: u64, sequence: IoSequence);
-    fn read_file(&self, path: &Path, file_size: u64, sequence: IoSequence);
+    fn write_file(&self, workload: &Workload);
+    fn read_file(&self, workload: &Workload);
+    fn replay(&self, workload: &Workload, trace: &[TraceOp]);
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Report {
+    items: Vec<ReportItem>,
+    #[serde(default)]
+    small_files: Vec<SmallFilesReportItem>,
+    #[serde(default)]
+    replay: Vec<ReplayReportItem>,
+    #[serde(default)]
+    environment: Option<RunEnvironment>,
+}
+
+fn legacy_preallocate() -> Option<Preallocate> {
+    Some(Preallocate::Sparse)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ReportItem {
+    // set when reports from several machines are merged
+    #[serde(default)]
+    machine: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
     method: IoMethodSettings,
     sequence: IoSequence,
+    #[serde(default = "legacy_preallocate")]
+    preallocate: Option<Preallocate>,
+    #[serde(default)]
+    file_layout: FileLayout,
     write_tput_mbps: f64,
+    #[serde(default)]
+    overwrite_tput_mbps: Option<f64>,
     read_tput_mbps: f64,
+    #[serde(default)]
+    open_loop: Vec<OpenLoopPoint>,
+    // from an extra pass when the settings ask for it, see `BenchSettings::measure_latency`
+    #[serde(default)]
+    write_latency: Option<LatencySummary>,
+    #[serde(default)]
+    read_latency: Option<LatencySummary>,
+    #[serde(default)]
+    write_cpu: Option<CpuUsage>,
+    #[serde(default)]
+    overwrite_cpu: Option<CpuUsage>,
+    #[serde(default)]
+    read_cpu: Option<CpuUsage>,
+    #[serde(default)]
+    write_disk: Option<DiskStats>,
+    #[serde(default)]
+    overwrite_disk: Option<DiskStats>,
+    #[serde(default)]
+    read_disk: Option<DiskStats>,
+    #[serde(default)]
+    write_page_cache: Option<PageCacheStats>,
+    #[serde(default)]
+    overwrite_page_cache: Option<PageCacheStats>,
+    #[serde(default)]
+    read_page_cache: Option<PageCacheStats>,
+    #[serde(default)]
+    write_samples: Vec<ThroughputSample>,
+    #[serde(default)]
+    overwrite_samples: Vec<ThroughputSample>,
+    #[serde(default)]
+    read_samples: Vec<ThroughputSample>,
+    // throughput of each pass, for significance tests in `compare`
+    #[serde(default)]
+    write_pass_mbps: Vec<f64>,
+    #[serde(default)]
+    overwrite_pass_mbps: Vec<f64>,
+    #[serde(default)]
+    read_pass_mbps: Vec<f64>,
+    // RWF_NOWAIT calls per pass that returned EAGAIN and were retried blocking
+    #[serde(default)]
+    write_nowait_fallbacks: Option<f64>,
+    #[serde(default)]
+    overwrite_nowait_fallbacks: Option<f64>,
+    #[serde(default)]
+    read_nowait_fallbacks: Option<f64>,
+    #[serde(default)]
+    steady_state: Vec<SteadyStateResult>,
+}
+
+impl ReportItem {
+    // An item with nothing measured yet.
+    fn new(
+        method: IoMethodSettings,
+        sequence: IoSequence,
+        preallocate: Option<Preallocate>,
+        file_layout: FileLayout,
+    ) -> ReportItem {
+        ReportItem {
+            machine: None,
+            target: None,
+            method,
+            sequence,
+            preallocate,
+            file_layout,
+            write_tput_mbps: 0.0,
+            overwrite_tput_mbps: None,
+            read_tput_mbps: 0.0,
+            open_loop: Vec::new(),
+            write_latency: None,
+            read_latency: None,
+            write_cpu: None,
+            overwrite_cpu: None,
+            read_cpu: None,
+            write_disk: None,
+            overwrite_disk: None,
+            read_disk: None,
+            write_page_cache: None,
+            overwrite_page_cache: None,
+            read_page_cache: None,
+            write_samples: Vec::new(),
+            overwrite_samples: Vec::new(),
+            read_samples: Vec::new(),
+            write_pass_mbps: Vec::new(),
+            overwrite_pass_mbps: Vec::new(),
+            read_pass_mbps: Vec::new(),
+            write_nowait_fallbacks: None,
+            overwrite_nowait_fallbacks: None,
+            read_nowait_fallbacks: None,
+            steady_state: Vec::new(),
+        }
+    }
 }
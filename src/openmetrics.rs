@@ -0,0 +1,500 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    Report, ReportItem,
+    bench_settings::{FileLayout, IoMethodSettings, rw_flags_label},
+    io_data::Vectored,
+    latency::LatencySummary,
+};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Metric families in output order; OpenMetrics doesn't allow a family's samples to be interleaved
+// with another's.
+#[derive(Default)]
+struct Families {
+    families: BTreeMap<&'static str, Family>,
+    order: Vec<&'static str>,
+}
+
+struct Family {
+    metric_type: &'static str,
+    unit: Option<&'static str>,
+    help: &'static str,
+    samples: Vec<String>,
+    // label sets already written, reports can repeat the same settings
+    seen: BTreeSet<String>,
+}
+
+impl Families {
+    fn family(
+        &mut self,
+        name: &'static str,
+        metric_type: &'static str,
+        unit: Option<&'static str>,
+        help: &'static str,
+    ) -> &mut Family {
+        if !self.families.contains_key(name) {
+            self.order.push(name);
+        }
+        self.families.entry(name).or_insert_with(|| Family {
+            metric_type,
+            unit,
+            help,
+            samples: Vec::new(),
+            seen: BTreeSet::new(),
+        })
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        unit: Option<&'static str>,
+        help: &'static str,
+        labels: &[(&str, String)],
+        value: f64,
+    ) {
+        let family = self.family(name, "gauge", unit, help);
+        let labels = format_labels(labels);
+        if family.seen.insert(labels.clone()) {
+            family.samples.push(format!("{name}{labels} {value}"));
+        }
+    }
+
+    fn latency(&mut self, labels: &[(&str, String)], latency: &LatencySummary) {
+        self.gauge(
+            "io_benchmark_latency_max_seconds",
+            Some("seconds"),
+            "Highest per-operation latency from the latency pass, open loop and trace replay runs.",
+            labels,
+            latency.max_us / 1e6,
+        );
+        let name = "io_benchmark_latency_seconds";
+        let family = self.family(
+            name,
+            "summary",
+            Some("seconds"),
+            "Per-operation latency from the latency pass, open loop and trace replay runs.",
+        );
+        if !family.seen.insert(format_labels(labels)) {
+            return;
+        }
+        for (quantile, us) in [("0.5", latency.p50_us), ("0.99", latency.p99_us)] {
+            let mut quantile_labels = labels.to_vec();
+            quantile_labels.push(("quantile", quantile.to_string()));
+            family.samples.push(format!(
+                "{name}{labels} {value}",
+                labels = format_labels(&quantile_labels),
+                value = us / 1e6
+            ));
+        }
+        let labels = format_labels(labels);
+        family.samples.push(format!(
+            "{name}_sum{labels} {value}",
+            value = latency.mean_us * latency.count as f64 / 1e6
+        ));
+        family
+            .samples
+            .push(format!("{name}_count{labels} {}", latency.count));
+    }
+}
+
+// The report as an OpenMetrics text exposition.
+pub fn openmetrics(report: &Report) -> String {
+    let mut families = Families::default();
+    for item in &report.items {
+        let labels = item_labels(item);
+        for (op, mbps) in [
+            ("write", Some(item.write_tput_mbps)),
+            ("overwrite", item.overwrite_tput_mbps),
+            ("read", Some(item.read_tput_mbps)),
+        ] {
+            let Some(mbps) = mbps else {
+                continue;
+            };
+            let mut labels = labels.clone();
+            labels.push(("op", op.to_string()));
+            let bytes_per_sec = mbps * 1024.0 * 1024.0;
+            families.gauge(
+                "io_benchmark_throughput_bytes_per_second",
+                Some("bytes_per_second"),
+                "Throughput of a full pass over the test files, averaged over the passes.",
+                &labels,
+                bytes_per_sec,
+            );
+            families.gauge(
+                "io_benchmark_iops",
+                None,
+                "Operations per second, throughput divided by the block size.",
+                &labels,
+                bytes_per_sec / item.method.block_size() as f64,
+            );
+        }
+        for (op, latency) in [("write", &item.write_latency), ("read", &item.read_latency)] {
+            if let Some(latency) = latency {
+                let mut labels = labels.clone();
+                labels.push(("op", op.to_string()));
+                labels.push(("source", "latency_pass".to_string()));
+                families.latency(&labels, latency);
+            }
+        }
+        for point in &item.open_loop {
+            let mut labels = labels.clone();
+            labels.push(("op", format!("{:?}", point.op).to_lowercase()));
+            labels.push(("source", "open_loop".to_string()));
+            labels.push(("target_iops", point.target_iops.to_string()));
+            families.latency(&labels, &point.latency);
+        }
+    }
+    for item in &report.replay {
+        let labels = vec![
+            ("machine", item.machine.clone().unwrap_or_default()),
+            ("target", item.target.clone().unwrap_or_default()),
+            ("method", item.method.name().to_string()),
+            ("block_size", item.method.block_size().to_string()),
+            ("concurrency", item.method.concurrency().to_string()),
+            ("trace", item.trace_file.display().to_string()),
+            ("timing", serde_name(item.timing)),
+        ];
+        families.gauge(
+            "io_benchmark_replay_throughput_bytes_per_second",
+            Some("bytes_per_second"),
+            "Throughput of a trace replay.",
+            &labels,
+            item.tput_mbps * 1024.0 * 1024.0,
+        );
+        families.gauge(
+            "io_benchmark_replay_iops",
+            None,
+            "Operations per second of a trace replay.",
+            &labels,
+            item.iops,
+        );
+        for (op, latency) in [("read", &item.read_latency), ("write", &item.write_latency)] {
+            if let Some(latency) = latency {
+                let mut labels = labels.clone();
+                labels.push(("op", op.to_string()));
+                labels.push(("source", "replay".to_string()));
+                families.latency(&labels, latency);
+            }
+        }
+    }
+
+    let mut text = String::new();
+    for name in &families.order {
+        let family = &families.families[name];
+        writeln!(text, "# TYPE {name} {}", family.metric_type).unwrap();
+        if let Some(unit) = family.unit {
+            writeln!(text, "# UNIT {name} {unit}").unwrap();
+        }
+        writeln!(text, "# HELP {name} {}", family.help).unwrap();
+        for sample in &family.samples {
+            writeln!(text, "{sample}").unwrap();
+        }
+    }
+    text.push_str("# EOF\n");
+    text
+}
+
+fn item_labels(item: &ReportItem) -> Vec<(&'static str, String)> {
+    vec![
+        ("machine", item.machine.clone().unwrap_or_default()),
+        ("target", item.target.clone().unwrap_or_default()),
+        ("method", item.method.name().to_string()),
+        ("block_size", item.method.block_size().to_string()),
+        ("concurrency", item.method.concurrency().to_string()),
+        ("iovecs", vectored_label(&item.method, |v| v.iovecs)),
+        (
+            "segment_size",
+            vectored_label(&item.method, |v| v.segment_size),
+        ),
+        ("rw_flags", rw_flags_label(item.method.rw_flags())),
+        ("sequence", format!("{:?}", item.sequence).to_lowercase()),
+        (
+            "preallocate",
+            item.preallocate.map_or(String::new(), serde_name),
+        ),
+        (
+            "file_layout",
+            match item.file_layout {
+                FileLayout::RandomFile { files } => format!("random_file:{files}"),
+                file_layout => serde_name(file_layout),
+            },
+        ),
+    ]
+}
+
+// Empty, so left out, for contiguous buffers.
+fn vectored_label(method: &IoMethodSettings, field: fn(Vectored) -> u32) -> String {
+    method
+        .vectored()
+        .map_or(String::new(), |vectored| field(vectored).to_string())
+}
+
+// The name a unit enum variant, or the tag of an internally tagged one, has in the settings.
+fn serde_name(value: impl Serialize) -> String {
+    match serde_json::to_value(value).unwrap() {
+        serde_json::Value::String(name) => name,
+        value => value["type"].as_str().unwrap().to_string(),
+    }
+}
+
+// Empty values are left out, Prometheus treats them as missing labels anyway.
+fn format_labels(labels: &[(&str, String)]) -> String {
+    let labels = labels
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+// Serves `body` at /metrics until the process is killed, one connection at a time.
+pub fn serve(addr: &str, body: &str) {
+    let listener = TcpListener::bind(addr).unwrap();
+    println!(
+        "serving metrics at http://{addr}/metrics",
+        addr = listener.local_addr().unwrap()
+    );
+    serve_on(&listener, body, READ_TIMEOUT);
+}
+
+fn serve_on(listener: &TcpListener, body: &str, read_timeout: Duration) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // a client that connects and sends nothing would block every later scrape
+        if stream.set_read_timeout(Some(read_timeout)).is_err() {
+            continue;
+        }
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            continue;
+        }
+        // skip the headers, there's no request body to read for GET
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+            header.clear();
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or("");
+        let response = if request_line.starts_with("GET ") && path == "/metrics" {
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n{body}",
+                len = body.len()
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+        // a scraper that hung up early isn't our problem
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpStream, thread};
+
+    use super::*;
+    use crate::{
+        IoOp, IoSequence, buffered_io::Buffered, direct_io_uring::DirectUring,
+        pacing::OpenLoopPoint,
+    };
+
+    fn latency(count: u64) -> LatencySummary {
+        LatencySummary {
+            count,
+            mean_us: 100.0,
+            p50_us: 80.0,
+            p99_us: 500.0,
+            max_us: 2000.0,
+        }
+    }
+
+    fn report() -> Report {
+        let mut buffered = ReportItem::new(
+            IoMethodSettings::Buffered(Buffered {
+                block_size: 4096,
+                vectored: None,
+                rw_flags: Vec::new(),
+            }),
+            IoSequence::Sequential,
+            None,
+            FileLayout::Single,
+        );
+        buffered.target = Some("nvme \"fast\"\\1".to_string());
+        buffered.write_tput_mbps = 100.0;
+        buffered.overwrite_tput_mbps = Some(50.0);
+        buffered.read_tput_mbps = 200.0;
+        buffered.write_latency = Some(latency(1000));
+        buffered.read_latency = Some(latency(2000));
+        let mut uring = ReportItem::new(
+            IoMethodSettings::DirectUring(DirectUring {
+                block_size: 65536,
+                concurrency: 16,
+                vectored: Some(Vectored {
+                    iovecs: 4,
+                    segment_size: 16384,
+                }),
+            }),
+            IoSequence::Random,
+            None,
+            FileLayout::Single,
+        );
+        uring.write_tput_mbps = 1000.0;
+        uring.read_tput_mbps = 2000.0;
+        uring.open_loop = vec![OpenLoopPoint {
+            op: IoOp::Read,
+            target_iops: 5000.0,
+            achieved_iops: 4999.0,
+            latency: latency(15000),
+        }];
+        Report {
+            // the same settings twice, as when reports are merged
+            items: vec![buffered.clone(), uring, buffered],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn families_are_contiguous() {
+        let text = openmetrics(&report());
+        assert!(text.ends_with("\n# EOF\n"));
+        let mut families = Vec::<&str>::new();
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let name = rest.split(' ').next().unwrap();
+                assert!(!families.contains(&name), "{name} declared twice");
+                families.push(name);
+                continue;
+            }
+            if line.starts_with("# ") {
+                continue;
+            }
+            // every sample belongs to the family declared last
+            let family = families.last().unwrap();
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(
+                name.strip_prefix(family)
+                    .is_some_and(|suffix| ["", "_sum", "_count"].contains(&suffix)),
+                "{line} outside of its family {family}"
+            );
+        }
+        assert_eq!(
+            families,
+            [
+                "io_benchmark_throughput_bytes_per_second",
+                "io_benchmark_iops",
+                "io_benchmark_latency_max_seconds",
+                "io_benchmark_latency_seconds",
+            ]
+        );
+        for name in &families {
+            assert_eq!(text.matches(&format!("# HELP {name} ")).count(), 1);
+        }
+        assert_eq!(
+            text.matches("# UNIT io_benchmark_latency_seconds seconds\n")
+                .count(),
+            1
+        );
+        assert!(!text.contains("# UNIT io_benchmark_iops"));
+    }
+
+    #[test]
+    fn samples() {
+        let text = openmetrics(&report());
+        // empty labels such as machine are left out, quotes and backslashes are escaped
+        let buffered = r#"target="nvme \"fast\"\\1",method="buffered",block_size="4096",concurrency="1",sequence="sequential",file_layout="single""#;
+        // the repeated item is written once
+        assert_eq!(
+            text.matches(&format!(
+                "io_benchmark_throughput_bytes_per_second{{{buffered},op=\"read\"}} 209715200\n"
+            ))
+            .count(),
+            1
+        );
+        assert!(text.contains(&format!(
+            "io_benchmark_iops{{{buffered},op=\"write\"}} 25600\n"
+        )));
+        let uring = r#"method="direct_uring",block_size="65536",concurrency="16",iovecs="4",segment_size="16384",sequence="random",file_layout="single",op="read",source="open_loop",target_iops="5000""#;
+        for sample in [
+            format!("io_benchmark_latency_seconds{{{uring},quantile=\"0.5\"}} 0.00008\n"),
+            format!("io_benchmark_latency_seconds{{{uring},quantile=\"0.99\"}} 0.0005\n"),
+            format!("io_benchmark_latency_seconds_sum{{{uring}}} 1.5\n"),
+            format!("io_benchmark_latency_seconds_count{{{uring}}} 15000\n"),
+            format!("io_benchmark_latency_max_seconds{{{uring}}} 0.002\n"),
+        ] {
+            assert!(text.contains(&sample), "missing {sample}");
+        }
+        assert!(text.contains(&format!(
+            "io_benchmark_latency_seconds_count{{{buffered},op=\"write\",source=\"latency_pass\"}} 1000\n"
+        )));
+    }
+
+    #[test]
+    fn empty_report() {
+        assert_eq!(openmetrics(&Report::default()), "# EOF\n");
+    }
+
+    fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let body = openmetrics(&report());
+        let served = body.clone();
+        thread::spawn(move || serve_on(&listener, &served, Duration::from_millis(100)));
+
+        // a client that never sends a request doesn't block the scrapes after it
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let response = get(addr, "/metrics");
+        let (head, content) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.lines();
+        assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+        let headers = lines.collect::<Vec<_>>();
+        assert!(
+            headers.contains(
+                &"Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8"
+            )
+        );
+        assert!(headers.contains(&format!("Content-Length: {}", body.len()).as_str()));
+        assert_eq!(content, body);
+
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Length: 0\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use io_uring::{IoUring, types};
+use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{IoOp, latency::LatencySummary};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenLoopSettings {
+    pub rates: Vec<Rate>,
+    pub arrivals: Arrivals,
+    #[serde(default = "default_duration_secs")]
+    pub duration_secs: f64,
+}
+
+fn default_duration_secs() -> f64 {
+    3.0
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rate {
+    Iops(f64),
+    Mbps(f64),
+}
+
+impl Rate {
+    pub fn iops(&self, block_size: u32) -> f64 {
+        match self {
+            Rate::Iops(iops) => *iops,
+            Rate::Mbps(mbps) => mbps * 1024.0 * 1024.0 / block_size as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arrivals {
+    Uniform,
+    Poisson,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OpenLoopPoint {
+    pub op: IoOp,
+    pub target_iops: f64,
+    pub achieved_iops: f64,
+    pub latency: LatencySummary,
+}
+
+// Hands out intended issue times, either at a fixed average rate or following a recorded trace.
+// Latency is measured from the intended issue time rather than from the actual submission, so a
+// stalled device shows up as growing latency instead of as fewer requests (coordinated omission).
+pub struct Pacer {
+    schedule: Schedule,
+    op_limit: u64,
+    state: Mutex<PacerState>,
+}
+
+enum Schedule {
+    Rate {
+        arrivals: Arrivals,
+        interval: Duration,
+    },
+    // offsets from the first issued operation
+    Offsets(Vec<Duration>),
+    Immediate,
+}
+
+struct PacerState {
+    start: Option<Instant>,
+    next_issue: Option<Instant>,
+    issued: usize,
+    latencies: BTreeMap<IoOp, Vec<Duration>>,
+}
+
+impl Pacer {
+    pub fn new(rate_iops: f64, arrivals: Arrivals, op_limit: u64) -> Pacer {
+        Pacer::with_schedule(
+            Schedule::Rate {
+                arrivals,
+                interval: Duration::from_secs_f64(1.0 / rate_iops),
+            },
+            op_limit,
+        )
+    }
+
+    pub fn from_offsets(offsets: Vec<Duration>) -> Pacer {
+        let op_limit = offsets.len() as u64;
+        Pacer::with_schedule(Schedule::Offsets(offsets), op_limit)
+    }
+
+    // Issues every operation as soon as possible but still records latencies.
+    pub fn immediate(op_limit: u64) -> Pacer {
+        Pacer::with_schedule(Schedule::Immediate, op_limit)
+    }
+
+    fn with_schedule(schedule: Schedule, op_limit: u64) -> Pacer {
+        Pacer {
+            schedule,
+            op_limit,
+            state: Mutex::new(PacerState {
+                start: None,
+                next_issue: None,
+                issued: 0,
+                latencies: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn op_limit(&self) -> u64 {
+        self.op_limit
+    }
+
+    pub fn next_issue_time(&self) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let start = *state.start.get_or_insert_with(Instant::now);
+        state.issued += 1;
+        match &self.schedule {
+            Schedule::Rate { arrivals, interval } => {
+                let issue = state.next_issue.unwrap_or(start);
+                let gap = match arrivals {
+                    Arrivals::Uniform => *interval,
+                    Arrivals::Poisson => interval.mul_f64(-(1.0 - rng().random::<f64>()).ln()),
+                };
+                state.next_issue = Some(issue + gap);
+                issue
+            }
+            Schedule::Offsets(offsets) => start + offsets[state.issued - 1],
+            Schedule::Immediate => Instant::now(),
+        }
+    }
+
+    pub fn record(&self, op: IoOp, intended: Instant) {
+        self.state
+            .lock()
+            .unwrap()
+            .latencies
+            .entry(op)
+            .or_default()
+            .push(intended.elapsed());
+    }
+
+    pub fn into_latencies(self) -> BTreeMap<IoOp, Vec<Duration>> {
+        self.state.into_inner().unwrap().latencies
+    }
+}
+
+pub fn sleep_until(deadline: Instant) {
+    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+// Waits for at least one completion, or until `deadline` passes if one is given.
+pub fn uring_wait(uring: &IoUring, deadline: Option<Instant>) {
+    let Some(deadline) = deadline else {
+        uring.submit_and_wait(1).unwrap();
+        return;
+    };
+    let timespec = types::Timespec::from(deadline.saturating_duration_since(Instant::now()));
+    let args = types::SubmitArgs::new().timespec(&timespec);
+    match uring.submitter().submit_with_args(1, &args) {
+        Ok(_) => {}
+        Err(e) if e.raw_os_error() == Some(libc::ETIME) => {}
+        Err(e) => panic!("io_uring_enter: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets_from_first(pacer: &Pacer, count: usize) -> Vec<Duration> {
+        let first = pacer.next_issue_time();
+        std::iter::once(Duration::ZERO)
+            .chain((1..count).map(|_| pacer.next_issue_time() - first))
+            .collect()
+    }
+
+    #[test]
+    fn uniform_issue_times() {
+        let pacer = Pacer::new(1000.0, Arrivals::Uniform, 5);
+        assert_eq!(
+            offsets_from_first(&pacer, 5),
+            (0..5).map(Duration::from_millis).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn trace_issue_times() {
+        let offsets = [0, 0, 5, 250].map(Duration::from_millis).to_vec();
+        let pacer = Pacer::from_offsets(offsets.clone());
+        assert_eq!(pacer.op_limit(), 4);
+        assert_eq!(offsets_from_first(&pacer, 4), offsets);
+    }
+
+    #[test]
+    fn poisson_issue_times_keep_order() {
+        let pacer = Pacer::new(1000.0, Arrivals::Poisson, 100);
+        let offsets = offsets_from_first(&pacer, 100);
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn late_issuers_catch_up() {
+        // the schedule doesn't slip when the issuer falls behind, the missed operations are due
+        // at once
+        let pacer = Pacer::new(1000.0, Arrivals::Uniform, 100);
+        let first = pacer.next_issue_time();
+        std::thread::sleep(Duration::from_millis(20));
+        let now = Instant::now();
+        let late = (0..10).map(|_| pacer.next_issue_time()).collect::<Vec<_>>();
+        assert_eq!(late[0] - first, Duration::from_millis(1));
+        assert_eq!(late[9] - first, Duration::from_millis(10));
+        assert!(late.iter().all(|&issue| issue < now));
+    }
+
+    #[test]
+    fn latency_from_intended_issue_time() {
+        let pacer = Pacer::new(1000.0, Arrivals::Uniform, 2);
+        let intended = pacer.next_issue_time();
+        std::thread::sleep(Duration::from_millis(20));
+        // submitted late and completed at once, the wait counts
+        let submitted = Instant::now();
+        pacer.record(IoOp::Read, intended);
+        let latencies = pacer.into_latencies();
+        assert_eq!(latencies.keys().collect::<Vec<_>>(), [&IoOp::Read]);
+        let latency = latencies[&IoOp::Read][0];
+        assert!(latency >= Duration::from_millis(20), "{latency:?}");
+        assert!(latency >= submitted - intended);
+    }
+
+    #[test]
+    fn immediate_issue_times() {
+        let pacer = Pacer::immediate(3);
+        let before = Instant::now();
+        let issue = pacer.next_issue_time();
+        assert!(issue >= before && issue <= Instant::now());
+    }
+}
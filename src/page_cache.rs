@@ -0,0 +1,232 @@
+use std::{
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{disk_stats::BlockDevice, io_data::Workload};
+
+// Page cache state right after the last pass of a measurement.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PageCacheStats {
+    pub resident_bytes: u64,
+    pub resident_fraction: f64,
+    pub dirty_kib: u64,
+    pub writeback_kib: u64,
+    pub read_ahead_kb: Option<u32>,
+    // the advice standing in for `readahead_kb` when sysfs wasn't writable
+    #[serde(default)]
+    pub fadvise: Option<Fadvise>,
+}
+
+impl PageCacheStats {
+    pub fn now(workload: &Workload, device: Option<BlockDevice>) -> PageCacheStats {
+        let resident_bytes = workload
+            .paths
+            .iter()
+            .map(|path| resident_bytes(path, workload.file_size))
+            .sum::<u64>();
+        let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap();
+        PageCacheStats {
+            resident_bytes,
+            resident_fraction: resident_bytes as f64 / workload.total_size() as f64,
+            dirty_kib: meminfo_kib(&meminfo, "Dirty"),
+            writeback_kib: meminfo_kib(&meminfo, "Writeback"),
+            read_ahead_kb: device.and_then(read_ahead_path).map(|path| {
+                std::fs::read_to_string(path)
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap()
+            }),
+            fadvise: workload.fadvise,
+        }
+    }
+}
+
+// Counts the pages of the first `len` bytes of `path` that are in page cache.
+fn resident_bytes(path: &Path, len: u64) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+    let file = File::open(path).unwrap();
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    // SAFETY: maps a fresh read-only region that is only passed to mincore and unmapped below
+    let addr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len as usize,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    assert_ne!(
+        addr,
+        libc::MAP_FAILED,
+        "mmap {path}: {err}",
+        path = path.display(),
+        err = std::io::Error::last_os_error()
+    );
+    let mut pages = vec![0u8; len.div_ceil(page_size) as usize];
+    // SAFETY: addr maps len bytes and pages holds one byte per page of the mapping
+    let rc = unsafe { libc::mincore(addr, len as usize, pages.as_mut_ptr()) };
+    assert_eq!(rc, 0, "mincore: {}", std::io::Error::last_os_error());
+    // SAFETY: addr and len are the mapping created above
+    unsafe {
+        libc::munmap(addr, len as usize);
+    }
+    pages.iter().filter(|&&page| page & 1 != 0).count() as u64 * page_size
+}
+
+fn meminfo_kib(meminfo: &str, field: &str) -> u64 {
+    meminfo
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name != field {
+                return None;
+            }
+            value.trim().trim_end_matches("kB").trim().parse().ok()
+        })
+        .unwrap_or(0)
+}
+
+// Partitions don't have a queue directory of their own, it belongs to the parent disk.
+fn read_ahead_path(device: BlockDevice) -> Option<PathBuf> {
+    let sys_path = device.sys_path();
+    [
+        sys_path.join("queue/read_ahead_kb"),
+        sys_path.join("../queue/read_ahead_kb"),
+    ]
+    .into_iter()
+    .find(|path| path.exists())
+}
+
+// Overrides the readahead of the backing device while alive. Writing read_ahead_kb needs root; if
+// that fails, the backends approximate the setting with posix_fadvise on their own file
+// descriptors, since readahead state is per open file.
+pub struct ReadaheadOverride {
+    sysfs: Option<(PathBuf, String)>,
+    pub fadvise: Option<Fadvise>,
+}
+
+impl ReadaheadOverride {
+    pub fn new(device: Option<BlockDevice>, readahead_kb: u32) -> ReadaheadOverride {
+        let path = device.and_then(read_ahead_path);
+        let mut original = None;
+        if let Some(path) = &path {
+            let current = std::fs::read_to_string(path).unwrap();
+            match std::fs::write(path, readahead_kb.to_string()) {
+                Ok(()) => {
+                    println!(
+                        "read_ahead_kb {current} -> {readahead_kb} ({path})",
+                        current = current.trim(),
+                        path = path.display()
+                    );
+                    return ReadaheadOverride {
+                        sysfs: Some((path.clone(), current)),
+                        fadvise: None,
+                    };
+                }
+                Err(e) => println!("can't write {path}: {e}", path = path.display()),
+            }
+            original = current.trim().parse::<u32>().ok();
+        }
+        let current_kb = original.unwrap_or(128);
+        let advice = Fadvise::approximating(readahead_kb, current_kb);
+        eprintln!(
+            "warning: readahead_kb {readahead_kb} can't be applied exactly, using posix_fadvise \
+             {advice:?} instead (device readahead {current_kb} KiB)"
+        );
+        ReadaheadOverride {
+            sysfs: None,
+            fadvise: Some(advice),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fadvise {
+    Normal,
+    Sequential,
+    Random,
+}
+
+impl Fadvise {
+    // RANDOM disables readahead, SEQUENTIAL doubles the device default, NORMAL keeps it: there
+    // is no advice for any other size.
+    fn approximating(readahead_kb: u32, current_kb: u32) -> Fadvise {
+        if readahead_kb == 0 {
+            Fadvise::Random
+        } else if readahead_kb > current_kb {
+            Fadvise::Sequential
+        } else {
+            Fadvise::Normal
+        }
+    }
+
+    fn advice(self) -> libc::c_int {
+        match self {
+            Fadvise::Normal => libc::POSIX_FADV_NORMAL,
+            Fadvise::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Fadvise::Random => libc::POSIX_FADV_RANDOM,
+        }
+    }
+}
+
+impl Drop for ReadaheadOverride {
+    fn drop(&mut self) {
+        if let Some((path, original)) = &self.sysfs {
+            std::fs::write(path, original.trim()).unwrap();
+        }
+    }
+}
+
+pub fn fadvise(file: &File, advice: Fadvise) {
+    // SAFETY: fd is valid for the lifetime of file
+    let rc = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice.advice()) };
+    assert_eq!(
+        rc,
+        0,
+        "posix_fadvise: {}",
+        std::io::Error::from_raw_os_error(rc)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fadvise_fallback() {
+        assert_eq!(Fadvise::approximating(0, 128), Fadvise::Random);
+        assert_eq!(Fadvise::approximating(0, 0), Fadvise::Random);
+        assert_eq!(Fadvise::approximating(256, 128), Fadvise::Sequential);
+        assert_eq!(Fadvise::approximating(4096, 128), Fadvise::Sequential);
+        assert_eq!(Fadvise::approximating(128, 128), Fadvise::Normal);
+        assert_eq!(Fadvise::approximating(64, 128), Fadvise::Normal);
+    }
+
+    #[test]
+    fn fadvise_is_reported() {
+        let stats: PageCacheStats = serde_json::from_str(
+            r#"{"resident_bytes": 0, "resident_fraction": 0.0, "dirty_kib": 0,
+                "writeback_kib": 0, "read_ahead_kb": 128}"#,
+        )
+        .unwrap();
+        assert_eq!(stats.fadvise, None);
+        let stats = PageCacheStats {
+            fadvise: Some(Fadvise::Sequential),
+            ..stats
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.contains(r#""fadvise":"sequential""#), "{json}");
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::ReportItem;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct PivotArgs {
+    /// For the formats other than html, print the pivot tables instead of all rows
+    #[clap(long)]
+    pub pivot: bool,
+    #[clap(long, value_enum, default_value_t = PivotAxis::BlockSize)]
+    pub pivot_rows: PivotAxis,
+    #[clap(long, value_enum, default_value_t = PivotAxis::Method)]
+    pub pivot_columns: PivotAxis,
+    /// One pivot table per value
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = [PivotValue::Write, PivotValue::Read])]
+    pub pivot_values: Vec<PivotValue>,
+}
+
+// The clap defaults, for reports rendered by other commands.
+impl Default for PivotArgs {
+    fn default() -> PivotArgs {
+        PivotArgs {
+            pivot: false,
+            pivot_rows: PivotAxis::BlockSize,
+            pivot_columns: PivotAxis::Method,
+            pivot_values: vec![PivotValue::Write, PivotValue::Read],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum PivotAxis {
+    Machine,
+    Target,
+    Method,
+    BlockSize,
+    Concurrency,
+    Sequence,
+    Preallocate,
+    FileLayout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PivotValue {
+    Write,
+    Overwrite,
+    Read,
+}
+
+#[derive(Serialize)]
+pub struct Pivot {
+    pub title: String,
+    pub row_axis: &'static str,
+    pub column_axis: &'static str,
+    pub columns: Vec<String>,
+    pub rows: Vec<PivotRow>,
+}
+
+#[derive(Serialize)]
+pub struct PivotRow {
+    pub key: String,
+    pub cells: Vec<Option<f64>>,
+    // index of the highest cell
+    pub winner: Option<usize>,
+}
+
+impl PivotAxis {
+    fn name(self) -> &'static str {
+        match self {
+            PivotAxis::Machine => "machine",
+            PivotAxis::Target => "target",
+            PivotAxis::Method => "method",
+            PivotAxis::BlockSize => "block_size",
+            PivotAxis::Concurrency => "concurrency",
+            PivotAxis::Sequence => "sequence",
+            PivotAxis::Preallocate => "preallocate",
+            PivotAxis::FileLayout => "file_layout",
+        }
+    }
+
+    // Numeric axes sort by number, the rest by name.
+    fn key(self, item: &ReportItem) -> (u32, String) {
+        match self {
+            PivotAxis::Machine => (0, item.machine.clone().unwrap_or_default()),
+            PivotAxis::Target => (0, item.target.clone().unwrap_or_default()),
+            PivotAxis::Method => (0, item.method.label()),
+            PivotAxis::BlockSize => (
+                item.method.block_size(),
+                item.method.block_size().to_string(),
+            ),
+            PivotAxis::Concurrency => (
+                item.method.concurrency(),
+                item.method.concurrency().to_string(),
+            ),
+            PivotAxis::Sequence => (0, format!("{:?}", item.sequence)),
+            PivotAxis::Preallocate => (
+                0,
+                item.preallocate
+                    .map_or(String::new(), |preallocate| format!("{preallocate:?}")),
+            ),
+            PivotAxis::FileLayout => (0, format!("{:?}", item.file_layout)),
+        }
+    }
+}
+
+impl PivotValue {
+    fn name(self) -> &'static str {
+        match self {
+            PivotValue::Write => "Write",
+            PivotValue::Overwrite => "Overwrite",
+            PivotValue::Read => "Read",
+        }
+    }
+
+    fn mbps(self, item: &ReportItem) -> Option<f64> {
+        match self {
+            PivotValue::Write => Some(item.write_tput_mbps),
+            PivotValue::Overwrite => item.overwrite_tput_mbps,
+            PivotValue::Read => Some(item.read_tput_mbps),
+        }
+    }
+}
+
+// One table per value; rows that land in the same cell because they differ in settings that
+// aren't pivot axes are reduced to their best throughput, like the charts.
+pub fn pivots(items: &[ReportItem], args: &PivotArgs) -> Vec<Pivot> {
+    args.pivot_values
+        .iter()
+        .map(|&value| pivot(items, args.pivot_rows, args.pivot_columns, value))
+        .collect()
+}
+
+fn pivot(
+    items: &[ReportItem],
+    row_axis: PivotAxis,
+    column_axis: PivotAxis,
+    value: PivotValue,
+) -> Pivot {
+    let columns = items
+        .iter()
+        .map(|item| column_axis.key(item))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut cells = BTreeMap::<(u32, String), Vec<Option<f64>>>::new();
+    for item in items {
+        let Some(mbps) = value.mbps(item) else {
+            continue;
+        };
+        let column = columns.binary_search(&column_axis.key(item)).unwrap();
+        let cell = &mut cells
+            .entry(row_axis.key(item))
+            .or_insert_with(|| vec![None; columns.len()])[column];
+        *cell = Some(cell.map_or(mbps, |best| best.max(mbps)));
+    }
+    let rows = cells
+        .into_iter()
+        .map(|((_, key), cells)| PivotRow {
+            key,
+            // the first of equal cells, max_by would pick the last
+            winner: cells
+                .iter()
+                .enumerate()
+                .filter_map(|(i, cell)| Some((i, (*cell)?)))
+                .rev()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(i, _)| i),
+            cells,
+        })
+        .collect();
+    Pivot {
+        title: format!(
+            "{value} throughput, MiB/s, by {rows} and {columns}",
+            value = value.name(),
+            rows = row_axis.name(),
+            columns = column_axis.name(),
+        ),
+        row_axis: row_axis.name(),
+        column_axis: column_axis.name(),
+        columns: columns.into_iter().map(|(_, name)| name).collect(),
+        rows,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        IoSequence,
+        bench_settings::{FileLayout, IoMethodSettings, Preallocate},
+        buffered_io::Buffered,
+        direct_io::Direct,
+        report_table::ReportTable,
+    };
+
+    fn item(
+        direct: bool,
+        block_size: u32,
+        preallocate: Preallocate,
+        write_tput_mbps: f64,
+        overwrite_tput_mbps: Option<f64>,
+    ) -> ReportItem {
+        let method = if direct {
+            IoMethodSettings::Direct(Direct {
+                block_size,
+                vectored: None,
+                rw_flags: Vec::new(),
+            })
+        } else {
+            IoMethodSettings::Buffered(Buffered {
+                block_size,
+                vectored: None,
+                rw_flags: Vec::new(),
+            })
+        };
+        let mut item = ReportItem::new(
+            method,
+            IoSequence::Sequential,
+            Some(preallocate),
+            FileLayout::Single,
+        );
+        item.write_tput_mbps = write_tput_mbps;
+        item.overwrite_tput_mbps = overwrite_tput_mbps;
+        item
+    }
+
+    // key, cells and winner
+    type Row<'a> = (&'a str, Vec<Option<f64>>, Option<usize>);
+
+    fn cells(pivot: &Pivot) -> Vec<Row<'_>> {
+        pivot
+            .rows
+            .iter()
+            .map(|row| (row.key.as_str(), row.cells.clone(), row.winner))
+            .collect()
+    }
+
+    #[test]
+    fn winner_per_row() {
+        let items = [
+            item(false, 65536, Preallocate::Sparse, 900.0, None),
+            item(true, 65536, Preallocate::Sparse, 1200.0, None),
+            item(false, 4096, Preallocate::Sparse, 700.0, None),
+            item(true, 4096, Preallocate::Sparse, 300.0, None),
+            // not a pivot axis, only the best of the two counts
+            item(true, 4096, Preallocate::Fallocate, 400.0, None),
+        ];
+        let by_block_size = pivot(
+            &items,
+            PivotAxis::BlockSize,
+            PivotAxis::Method,
+            PivotValue::Write,
+        );
+        assert_eq!(by_block_size.columns, ["buffered", "direct"]);
+        // numeric keys sort by number
+        assert_eq!(
+            cells(&by_block_size),
+            [
+                ("4096", vec![Some(700.0), Some(400.0)], Some(0)),
+                ("65536", vec![Some(900.0), Some(1200.0)], Some(1)),
+            ]
+        );
+        // transposed, the winners are the best block size per method
+        let by_method = pivot(
+            &items,
+            PivotAxis::Method,
+            PivotAxis::BlockSize,
+            PivotValue::Write,
+        );
+        assert_eq!(by_method.columns, ["4096", "65536"]);
+        assert_eq!(
+            cells(&by_method),
+            [
+                ("buffered", vec![Some(700.0), Some(900.0)], Some(1)),
+                ("direct", vec![Some(400.0), Some(1200.0)], Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_first_column() {
+        let items = [
+            item(false, 4096, Preallocate::Sparse, 500.0, None),
+            item(true, 4096, Preallocate::Sparse, 500.0, None),
+        ];
+        let pivot = pivot(
+            &items,
+            PivotAxis::BlockSize,
+            PivotAxis::Method,
+            PivotValue::Write,
+        );
+        assert_eq!(
+            cells(&pivot),
+            [("4096", vec![Some(500.0), Some(500.0)], Some(0))]
+        );
+    }
+
+    #[test]
+    fn missing_cells() {
+        let items = [
+            item(false, 4096, Preallocate::Sparse, 700.0, Some(650.0)),
+            item(true, 4096, Preallocate::Sparse, 300.0, None),
+            item(true, 65536, Preallocate::Sparse, 1200.0, Some(1100.0)),
+            item(false, 1048576, Preallocate::Sparse, 1500.0, None),
+        ];
+        let pivot = pivot(
+            &items,
+            PivotAxis::BlockSize,
+            PivotAxis::Method,
+            PivotValue::Overwrite,
+        );
+        // rows without any value are left out, empty cells never win
+        assert_eq!(
+            cells(&pivot),
+            [
+                ("4096", vec![Some(650.0), None], Some(0)),
+                ("65536", vec![None, Some(1100.0)], Some(1)),
+            ]
+        );
+        // the table highlights the winners after the key column
+        let table = ReportTable::from_pivot(&pivot);
+        assert_eq!(table.columns, ["block_size", "buffered", "direct"]);
+        assert_eq!(table.highlighted, [(0, 1), (1, 2)]);
+        assert!(table.to_markdown().contains("| 65536 |  | **1100.00** |"));
+    }
+}
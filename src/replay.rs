@@ -0,0 +1,476 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    IoMethod, IoOp, IoSequence,
+    bench_settings::{FileLayout, IoMethodSettings},
+    cpu_usage::{CpuSnapshot, CpuUsage},
+    disk_stats::{DiskSnapshot, DiskStats, backing_device},
+    io_data::Workload,
+    latency::LatencySummary,
+    pacing::Pacer,
+    page_cache::Fadvise,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplaySettings {
+    pub trace_file: PathBuf,
+    pub timing: ReplayTiming,
+    #[serde(default = "default_align")]
+    pub align: u32,
+    pub methods: Vec<IoMethodSettings>,
+}
+
+fn default_align() -> u32 {
+    4096
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayTiming {
+    AsFastAsPossible,
+    Original,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayReportItem {
+    #[serde(default)]
+    pub machine: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    pub trace_file: PathBuf,
+    pub timing: ReplayTiming,
+    pub method: IoMethodSettings,
+    pub ops: u64,
+    pub duration_secs: f64,
+    pub iops: f64,
+    pub tput_mbps: f64,
+    pub read_latency: Option<LatencySummary>,
+    pub write_latency: Option<LatencySummary>,
+    #[serde(default)]
+    pub cpu: Option<CpuUsage>,
+    #[serde(default)]
+    pub disk: Option<DiskStats>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceOp {
+    pub op: IoOp,
+    pub offset: u64,
+    pub len: u32,
+    // relative to the first operation of the trace
+    pub timestamp: Duration,
+}
+
+pub fn read_trace(settings: &ReplaySettings) -> Vec<TraceOp> {
+    let text = std::fs::read_to_string(&settings.trace_file).unwrap();
+    let trace = parse_trace(&text);
+    assert!(
+        !trace.is_empty(),
+        "no reads or writes found in {path}",
+        path = settings.trace_file.display()
+    );
+    trace
+}
+
+fn parse_trace(text: &str) -> Vec<TraceOp> {
+    let mut trace = match text.lines().next() {
+        Some("fio version 2 iolog") => parse_fio_iolog(text, false),
+        Some("fio version 3 iolog") => parse_fio_iolog(text, true),
+        _ => parse_blkparse(text),
+    };
+    if let Some(first) = trace.iter().map(|op| op.timestamp).min() {
+        for op in &mut trace {
+            op.timestamp -= first;
+        }
+    }
+    trace.sort_by_key(|op| op.timestamp);
+    trace
+}
+
+// Each line is `filename action [offset length]`, v3 prefixes it with a timestamp in
+// milliseconds. Only reads and writes are replayed; file management, syncs and trims are skipped.
+fn parse_fio_iolog(text: &str, has_timestamps: bool) -> Vec<TraceOp> {
+    let mut trace = Vec::new();
+    for (line_idx, line) in text.lines().enumerate().skip(1) {
+        let mut fields = line.split_whitespace();
+        let timestamp = if has_timestamps {
+            let Some(timestamp) = fields.next() else {
+                continue;
+            };
+            Duration::from_millis(parse_field(timestamp, line_idx))
+        } else {
+            Duration::ZERO
+        };
+        let (Some(_filename), Some(action)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let op = match action {
+            "read" => IoOp::Read,
+            "write" => IoOp::Write,
+            _ => continue,
+        };
+        let (Some(offset), Some(len)) = (fields.next(), fields.next()) else {
+            panic!(
+                "line {line_no}: expected offset and length",
+                line_no = line_idx + 1
+            );
+        };
+        let len = parse_field(len, line_idx);
+        assert!(
+            len > 0,
+            "line {line_no}: zero length {action}",
+            line_no = line_idx + 1
+        );
+        trace.push(TraceOp {
+            op,
+            offset: parse_field(offset, line_idx),
+            len,
+            timestamp,
+        });
+    }
+    trace
+}
+
+// Default blkparse output: `maj,min cpu seq timestamp pid action rwbs sector + sectors [process]`.
+// Queue (Q) events are replayed when present, otherwise driver issue (D) events.
+fn parse_blkparse(text: &str) -> Vec<TraceOp> {
+    let events = |wanted_action: &str| {
+        let mut trace = Vec::new();
+        for line in text.lines() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [
+                _dev,
+                _cpu,
+                _seq,
+                timestamp,
+                _pid,
+                action,
+                rwbs,
+                sector,
+                "+",
+                sectors,
+                ..,
+            ] = fields[..]
+            else {
+                continue;
+            };
+            if action != wanted_action {
+                continue;
+            }
+            let op = if rwbs.contains('R') {
+                IoOp::Read
+            } else if rwbs.contains('W') {
+                IoOp::Write
+            } else {
+                continue;
+            };
+            let (Ok(timestamp), Ok(sector), Ok(sectors)) = (
+                timestamp.parse::<f64>(),
+                sector.parse::<u64>(),
+                sectors.parse::<u32>(),
+            ) else {
+                continue;
+            };
+            if sectors == 0 {
+                continue;
+            }
+            trace.push(TraceOp {
+                op,
+                offset: sector * 512,
+                len: sectors * 512,
+                timestamp: Duration::from_secs_f64(timestamp),
+            });
+        }
+        trace
+    };
+    let queued = events("Q");
+    if queued.is_empty() {
+        events("D")
+    } else {
+        queued
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str, line_idx: usize) -> T {
+    match field.parse() {
+        Ok(value) => value,
+        Err(_) => panic!(
+            "line {line_no}: invalid number {field:?}",
+            line_no = line_idx + 1
+        ),
+    }
+}
+
+// Aligns operations for O_DIRECT and wraps their offsets into the test file. The backends size
+// their buffers for the longest operation, so the trace can't be empty or hold empty operations.
+pub fn fit_trace(trace: &mut [TraceOp], file_size: u64, align: u32) {
+    assert!(!trace.is_empty(), "empty trace");
+    let align = align.max(1) as u64;
+    for op in trace {
+        assert!(op.len > 0, "zero length {op:?} in the trace", op = op.op);
+        let len = (op.len as u64).div_ceil(align) * align;
+        assert!(
+            len <= file_size,
+            "trace operation of {len} bytes does not fit into file_size"
+        );
+        let offset = op.offset % file_size / align * align;
+        op.offset = offset.min(file_size - len) / align * align;
+        op.len = len as u32;
+    }
+}
+
+// Replays the trace against `path`, which must already hold `file_size` bytes.
+pub fn run_replay(
+    path: &Path,
+    file_size: u64,
+    fadvise: Option<Fadvise>,
+    settings: &ReplaySettings,
+    method: &IoMethodSettings,
+) -> ReplayReportItem {
+    let mut trace = read_trace(settings);
+    fit_trace(&mut trace, file_size, settings.align);
+    let pacer = match settings.timing {
+        ReplayTiming::AsFastAsPossible => Pacer::immediate(trace.len() as u64),
+        ReplayTiming::Original => {
+            Pacer::from_offsets(trace.iter().map(|op| op.timestamp).collect())
+        }
+    };
+    let paths = [path.to_path_buf()];
+    let workload = Workload {
+        paths: &paths,
+        file_size,
+        sequence: IoSequence::Sequential,
+        layout: FileLayout::Single,
+        pacer: Some(&pacer),
+        fadvise,
+        sampler: None,
+    };
+    let device = backing_device(path);
+    let disk_start = DiskSnapshot::now(device);
+    let cpu_start = CpuSnapshot::now();
+    let start = Instant::now();
+    method.replay(&workload, &trace);
+    let duration = start.elapsed();
+    let cpu = CpuSnapshot::now().usage_since(&cpu_start);
+    let disk = match (disk_start, DiskSnapshot::now(device)) {
+        (Some(disk_start), Some(disk_end)) => Some(disk_end.stats_since(&disk_start).per_pass(1)),
+        _ => None,
+    };
+    let bytes = trace.iter().map(|op| op.len as u64).sum::<u64>();
+    let mut latencies = pacer.into_latencies();
+    let mut latency = |op| {
+        latencies
+            .get_mut(&op)
+            .map(|samples| LatencySummary::from_samples(samples))
+    };
+    ReplayReportItem {
+        machine: None,
+        target: None,
+        trace_file: settings.trace_file.clone(),
+        timing: settings.timing,
+        method: method.clone(),
+        ops: trace.len() as u64,
+        duration_secs: duration.as_secs_f64(),
+        iops: trace.len() as f64 / duration.as_secs_f64(),
+        tput_mbps: bytes as f64 / 1024.0 / 1024.0 / duration.as_secs_f64(),
+        read_latency: latency(IoOp::Read),
+        write_latency: latency(IoOp::Write),
+        cpu: Some(cpu.per_pass(1, bytes)),
+        disk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(trace: &[TraceOp]) -> Vec<(IoOp, u64, u32, Duration)> {
+        trace
+            .iter()
+            .map(|op| (op.op, op.offset, op.len, op.timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn fio_iolog_v2() {
+        let trace = parse_trace(
+            "fio version 2 iolog\n\
+             /dev/sdb add\n\
+             /dev/sdb open\n\
+             /dev/sdb read 8192 4096\n\
+             /dev/sdb write 0 65536\n\
+             /dev/sdb sync 0 0\n\
+             /dev/sdb trim 4096 4096\n\
+             /dev/sdb close\n",
+        );
+        assert_eq!(
+            ops(&trace),
+            [
+                (IoOp::Read, 8192, 4096, Duration::ZERO),
+                (IoOp::Write, 0, 65536, Duration::ZERO),
+            ]
+        );
+    }
+
+    #[test]
+    fn fio_iolog_v3_timestamps_in_ms() {
+        let trace = parse_trace(
+            "fio version 3 iolog\n\
+             1000 /dev/sdb add\n\
+             1000 /dev/sdb open\n\
+             1250 /dev/sdb write 4096 4096\n\
+             1002 /dev/sdb read 0 4096\n\
+             3000 /dev/sdb close\n",
+        );
+        // relative to the first operation and sorted by time
+        assert_eq!(
+            ops(&trace),
+            [
+                (IoOp::Read, 0, 4096, Duration::ZERO),
+                (IoOp::Write, 4096, 4096, Duration::from_millis(248)),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2: expected offset and length")]
+    fn fio_iolog_missing_length() {
+        parse_trace("fio version 2 iolog\n/dev/sdb read 4096\n");
+    }
+
+    #[test]
+    fn blkparse_prefers_queue_events() {
+        let trace = parse_trace(
+            "8,16   1        1     0.000000000  4162  Q   R 2048 + 8 [fio]\n\
+             8,16   1        2     0.000002000  4162  G   R 2048 + 8 [fio]\n\
+             8,16   1        3     0.000010000  4162  D   R 2048 + 8 [fio]\n\
+             8,16   1        4     0.000500000  4162  Q  WS 4096 + 16 [fio]\n\
+             8,16   1        5     0.000600000  4162  D  WS 4096 + 16 [fio]\n\
+             8,16   1        6     0.000700000  4162  Q  FN [fio]\n\
+             8,16   1        7     0.000800000     0  C   R 2048 + 8 [0]\n\
+             CPU1 (fio):\n\
+             Reads Queued:           1,        4KiB\n",
+        );
+        assert_eq!(
+            ops(&trace),
+            [
+                (IoOp::Read, 2048 * 512, 8 * 512, Duration::ZERO),
+                (
+                    IoOp::Write,
+                    4096 * 512,
+                    16 * 512,
+                    Duration::from_micros(500)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn blkparse_falls_back_to_dispatch_events() {
+        let trace = parse_trace(
+            "8,0    0        1     1.500000000   212  D   W 100 + 8 [kworker]\n\
+             8,0    0        2     1.500100000     0  C   W 100 + 8 [0]\n\
+             8,0    0        3     1.750000000   212  D   R 300 + 0 [kworker]\n\
+             8,0    0        4     2.000000000   212  D  RA 200 + 256 [kworker]\n",
+        );
+        assert_eq!(
+            ops(&trace),
+            [
+                (IoOp::Write, 100 * 512, 8 * 512, Duration::ZERO),
+                (IoOp::Read, 200 * 512, 256 * 512, Duration::from_millis(500)),
+            ]
+        );
+    }
+
+    fn op(offset: u64, len: u32) -> TraceOp {
+        TraceOp {
+            op: IoOp::Read,
+            offset,
+            len,
+            timestamp: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn fit_trace_wraps_and_aligns() {
+        let file_size = 64 * 1024;
+        let mut trace = [
+            // unaligned, rounded down and up
+            op(5000, 100),
+            // past the end of the file, wrapped around
+            op(file_size + 8192, 4096),
+            op(10 * file_size + 512, 8192),
+            // would run past the end once wrapped, moved back
+            op(file_size - 4096, 16384),
+        ];
+        fit_trace(&mut trace, file_size, 4096);
+        assert_eq!(
+            trace
+                .iter()
+                .map(|op| (op.offset, op.len))
+                .collect::<Vec<_>>(),
+            [
+                (4096, 4096),
+                (8192, 4096),
+                (0, 8192),
+                (file_size - 16384, 16384)
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit into file_size")]
+    fn fit_trace_op_larger_than_file() {
+        fit_trace(&mut [op(0, 8192)], 4096, 4096);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty trace")]
+    fn fit_trace_empty() {
+        fit_trace(&mut [], 4096, 4096);
+    }
+
+    #[test]
+    #[should_panic(expected = "zero length Read in the trace")]
+    fn fit_trace_zero_length_op() {
+        fit_trace(&mut [op(0, 4096), op(4096, 0)], 64 * 1024, 512);
+    }
+
+    // Reads `text` as the trace file of a replay.
+    fn read_trace_text(name: &str, text: &str) -> Vec<TraceOp> {
+        let path = std::env::temp_dir().join(format!(
+            "io-experiments-{name}-{pid}.log",
+            pid = std::process::id()
+        ));
+        std::fs::write(&path, text).unwrap();
+        let settings = ReplaySettings {
+            trace_file: path.clone(),
+            timing: ReplayTiming::AsFastAsPossible,
+            align: 4096,
+            methods: Vec::new(),
+        };
+        let trace = std::panic::catch_unwind(|| read_trace(&settings));
+        std::fs::remove_file(&path).unwrap();
+        trace.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+
+    #[test]
+    #[should_panic(expected = "no reads or writes found")]
+    fn replay_of_empty_trace() {
+        read_trace_text(
+            "empty-trace",
+            "fio version 2 iolog\n/dev/sdb add\n/dev/sdb close\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2: zero length read")]
+    fn replay_of_zero_length_op() {
+        read_trace_text("zero-length-op", "fio version 2 iolog\n/dev/sdb read 0 0\n");
+    }
+}
@@ -1,30 +1,252 @@
-use minijinja::{Environment, context};
+use minijinja::context;
 
-use crate::ReportItem;
-use std::{cmp::Reverse, path::PathBuf};
+use serde::Deserialize;
+
+use crate::{
+    Report, ReportItem, charts,
+    openmetrics::openmetrics,
+    pivot::{PivotArgs, pivots},
+    report_index::{IndexCommand, run_index},
+    report_table::ReportTable,
+    run_environment::RunEnvironment,
+    templates,
+};
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 #[derive(Debug, Clone, clap::Args)]
 pub struct ReportCommand {
-    #[clap(long, value_parser, default_value = "target/report.json")]
-    report_file: PathBuf,
+    /// [LABEL=]PATH, may be repeated to merge reports from several machines into one
+    #[clap(
+        long = "report-file",
+        value_parser,
+        default_value = "target/report.json"
+    )]
+    report_files: Vec<ReportInput>,
     #[clap(long, value_parser, default_value = "target/report.html")]
     report_html_file: PathBuf,
+    #[clap(long, value_enum, default_value_t = ReportFormat::Html)]
+    format: ReportFormat,
+    /// Output file for the formats other than html, stdout by default
+    #[clap(long, value_parser)]
+    output_file: Option<PathBuf>,
+    /// Jinja template to use instead of the built-in report.jinja.html
+    #[clap(long, value_parser)]
+    template: Option<PathBuf>,
+    #[clap(flatten)]
+    pivot: PivotArgs,
+    #[clap(subcommand)]
+    command: Option<ReportSubcommand>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportInput {
+    label: Option<String>,
+    path: PathBuf,
+}
+
+impl FromStr for ReportInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once('=') {
+            Some((label, path)) => ReportInput {
+                label: Some(label.to_string()),
+                path: PathBuf::from(path),
+            },
+            None => ReportInput {
+                label: None,
+                path: PathBuf::from(s),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum ReportSubcommand {
+    /// Write an index page for a directory tree of reports
+    Index(IndexCommand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Csv,
+    Markdown,
+    Table,
+    Json,
+    Openmetrics,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReportFile {
+    Report(Box<Report>),
+    Legacy(Vec<ReportItem>),
+}
+
+pub fn read_report(path: &Path) -> Report {
+    parse_report(&std::fs::read(path).unwrap()).unwrap()
+}
+
+pub fn parse_report(data: &[u8]) -> serde_json::Result<Report> {
+    Ok(match serde_json::from_slice(data)? {
+        ReportFile::Report(report) => *report,
+        ReportFile::Legacy(items) => Report {
+            items,
+            ..Default::default()
+        },
+    })
+}
+
+pub fn sort_items(items: &mut [ReportItem]) {
+    items.sort_by_key(|ri| {
+        (
+            ri.machine.clone(),
+            ri.target.clone(),
+            Reverse((
+                ri.method.block_size(),
+                ri.sequence,
+                float_ord::FloatOrd(ri.write_tput_mbps + ri.read_tput_mbps),
+            )),
+        )
+    });
 }
 
 pub fn run_report(report_command: &ReportCommand) {
-    let mut report_items: Vec<ReportItem> =
-        serde_json::from_slice(&std::fs::read(&report_command.report_file).unwrap()).unwrap();
-    report_items.sort_by_key(|ri| {
-        Reverse((
-            ri.method.block_size(),
-            ri.sequence,
-            float_ord::FloatOrd(ri.write_tput_mbps + ri.read_tput_mbps),
+    if let Some(ReportSubcommand::Index(index_command)) = &report_command.command {
+        run_index(index_command);
+        return;
+    }
+    let (mut report, environments) = merge_reports(&report_command.report_files);
+    sort_items(&mut report.items);
+    if report_command.format == ReportFormat::Html {
+        write_html_report(
+            report,
+            environments,
+            report_command.template.as_deref(),
+            &report_command.pivot,
+            &report_command.report_html_file,
+        );
+        return;
+    }
+    let output = if report_command.format == ReportFormat::Openmetrics {
+        openmetrics(&report)
+    } else if report_command.pivot.pivot {
+        let pivots = pivots(&report.items, &report_command.pivot);
+        if report_command.format == ReportFormat::Json {
+            serde_json::to_string_pretty(&pivots).unwrap()
+        } else {
+            pivots
+                .iter()
+                .map(|pivot| {
+                    let table = ReportTable::from_pivot(pivot);
+                    match report_command.format {
+                        ReportFormat::Csv => format!("{}\n{}", pivot.title, table.to_csv()),
+                        ReportFormat::Markdown => {
+                            format!("### {}\n\n{}", pivot.title, table.to_markdown())
+                        }
+                        _ => format!("{}\n{}", pivot.title, table.to_text_table()),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    } else {
+        let table = ReportTable::new(&report.items);
+        match report_command.format {
+            ReportFormat::Csv => table.to_csv(),
+            ReportFormat::Markdown => table.to_markdown(),
+            ReportFormat::Json => table.to_json(),
+            _ => table.to_text_table(),
+        }
+    };
+    match &report_command.output_file {
+        Some(output_file) => std::fs::write(output_file, output).unwrap(),
+        None => print!("{output}"),
+    }
+}
+
+// Reads the inputs into one report. With several inputs, or an explicit label, every row is tagged
+// with its machine: the label, else the machine recorded by `run`, else the file name.
+fn merge_reports(inputs: &[ReportInput]) -> (Report, Vec<RunEnvironment>) {
+    let mut merged = Report::default();
+    let mut environments = Vec::new();
+    for input in inputs {
+        let report = read_report(&input.path);
+        let machine = (inputs.len() > 1 || input.label.is_some()).then(|| {
+            input
+                .label
+                .clone()
+                .or_else(|| {
+                    let environment = report.environment.as_ref()?;
+                    Some(environment.machine.clone()).filter(|machine| !machine.is_empty())
+                })
+                .unwrap_or_else(|| {
+                    input
+                        .path
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+        });
+        merged
+            .items
+            .extend(report.items.into_iter().map(|mut item| {
+                if machine.is_some() {
+                    item.machine.clone_from(&machine);
+                }
+                item
+            }));
+        merged
+            .small_files
+            .extend(report.small_files.into_iter().map(|mut item| {
+                if machine.is_some() {
+                    item.machine.clone_from(&machine);
+                }
+                item
+            }));
+        merged
+            .replay
+            .extend(report.replay.into_iter().map(|mut item| {
+                if machine.is_some() {
+                    item.machine.clone_from(&machine);
+                }
+                item
+            }));
+        if let Some(mut environment) = report.environment {
+            if let Some(machine) = &machine {
+                environment.machine.clone_from(machine);
+            }
+            environments.push(environment);
+        }
+    }
+    (merged, environments)
+}
+
+pub fn write_html_report(
+    report: Report,
+    environments: Vec<RunEnvironment>,
+    template: Option<&Path>,
+    pivot: &PivotArgs,
+    html_file: &Path,
+) {
+    let env = templates::environment(template, templates::REPORT_HTML);
+    let tmpl = env.get_template("main").unwrap();
+    let html = tmpl
+        .render(context!(
+            charts => charts::report_charts(&report.items),
+            pivots => pivots(&report.items, pivot),
+            report_items => report.items,
+            small_files => report.small_files,
+            replay => report.replay,
+            merged => report.items.iter().any(|item| item.machine.is_some()),
+            environments,
         ))
-    });
-    let mut env = Environment::new();
-    let template = std::fs::read_to_string("report.jinja.html").unwrap();
-    env.add_template("report", &template).unwrap();
-    let tmpl = env.get_template("report").unwrap();
-    let html = tmpl.render(context!(report_items => report_items)).unwrap();
-    std::fs::write(&report_command.report_html_file, html).unwrap();
+        .unwrap();
+    std::fs::write(html_file, html).unwrap();
 }
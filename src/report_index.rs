@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use minijinja::context;
+use serde::Serialize;
+
+use crate::{
+    Report,
+    compare::write_compare_html,
+    pivot::PivotArgs,
+    report::{parse_report, sort_items, write_html_report},
+    run_environment::{RunEnvironment, format_utc},
+    templates,
+};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct IndexCommand {
+    #[clap(long, value_parser, default_value = "results")]
+    results_dir: PathBuf,
+    /// Defaults to index.html in the results directory
+    #[clap(long, value_parser)]
+    index_file: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    label: String,
+    report_href: String,
+    date: String,
+    // legacy reports don't record when they ran, the file's modification time stands in
+    date_from_file: bool,
+    hostname: Option<String>,
+    kernel: Option<String>,
+    devices: Vec<String>,
+    best: Vec<MethodBest>,
+}
+
+#[derive(Serialize)]
+struct MethodBest {
+    method: &'static str,
+    write_mbps: f64,
+    read_mbps: f64,
+}
+
+#[derive(Serialize)]
+struct IndexComparison {
+    dir: String,
+    hostname: String,
+    href: String,
+    baseline: String,
+    runs: usize,
+}
+
+pub fn run_index(command: &IndexCommand) {
+    let index_file = command
+        .index_file
+        .clone()
+        .unwrap_or_else(|| command.results_dir.join("index.html"));
+    let index_dir = index_file.parent().unwrap_or(Path::new(""));
+
+    let mut report_files = Vec::new();
+    find_json_files(&command.results_dir, &mut report_files);
+    let mut entries = Vec::new();
+    // report files by directory and machine, each with its date
+    let mut runs_by_machine = BTreeMap::<(PathBuf, MachineKey), Vec<(String, PathBuf)>>::new();
+    for path in report_files {
+        let Ok(mut report) = parse_report(&std::fs::read(&path).unwrap()) else {
+            println!("skipping {path}, not a report", path = path.display());
+            continue;
+        };
+        let html_file = path.with_extension("html");
+        let entry = index_entry(&command.results_dir, &path, &html_file, index_dir, &report);
+        let machine = report.environment.as_ref().map(machine_key);
+        if !is_newer(&html_file, &path) {
+            println!("rendering {path}", path = html_file.display());
+            sort_items(&mut report.items);
+            let environments = report.environment.take().into_iter().collect();
+            write_html_report(
+                report,
+                environments,
+                None,
+                &PivotArgs::default(),
+                &html_file,
+            );
+        }
+        // legacy reports don't say where they ran, so they're never compared
+        if let Some(machine) = machine {
+            runs_by_machine
+                .entry((path.parent().unwrap().to_path_buf(), machine))
+                .or_default()
+                .push((entry.date.clone(), path));
+        }
+        entries.push(entry);
+    }
+
+    // two or more runs on the same host and targets in one directory get a page comparing them
+    // to the oldest; different hardware in a directory isn't a regression
+    let mut comparisons = Vec::new();
+    let mut pages_per_host = BTreeMap::<(PathBuf, String), usize>::new();
+    for ((dir, (hostname, _)), mut runs) in runs_by_machine {
+        if runs.len() < 2 {
+            continue;
+        }
+        runs.sort();
+        let page = pages_per_host
+            .entry((dir.clone(), hostname.clone()))
+            .or_default();
+        *page += 1;
+        let html_file = dir.join(match *page {
+            1 => format!("compare-{hostname}.html"),
+            page => format!("compare-{hostname}-{page}.html"),
+        });
+        let baseline = relative(&runs[0].1, &command.results_dir);
+        println!(
+            "comparing {count} reports from {hostname} in {dir}",
+            count = runs.len(),
+            dir = dir.display()
+        );
+        write_compare_html(
+            runs.iter().map(|(_, path)| path.clone()).collect(),
+            html_file.clone(),
+        );
+        comparisons.push(IndexComparison {
+            dir: relative(&dir, &command.results_dir),
+            hostname,
+            href: relative(&html_file, index_dir),
+            baseline,
+            runs: runs.len(),
+        });
+    }
+
+    let env = templates::environment(None, templates::INDEX_HTML);
+    let html = env
+        .get_template("main")
+        .unwrap()
+        .render(context!(entries, comparisons))
+        .unwrap();
+    std::fs::write(&index_file, html).unwrap();
+    println!(
+        "{count} reports indexed in {path}",
+        count = entries.len(),
+        path = index_file.display()
+    );
+}
+
+// The hostname and each target's label, device, model and filesystem.
+type MachineKey = (String, Vec<[Option<String>; 4]>);
+
+fn machine_key(environment: &RunEnvironment) -> MachineKey {
+    (
+        environment.hostname.clone(),
+        environment
+            .targets
+            .iter()
+            .map(|target| {
+                [
+                    target.label.clone(),
+                    target.device.clone(),
+                    target.model.clone(),
+                    target.filesystem.clone(),
+                ]
+            })
+            .collect(),
+    )
+}
+
+// False if `path` is missing or older than `source`.
+fn is_newer(path: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(path), modified(source)) {
+        (Ok(modified), Ok(source_modified)) => modified >= source_modified,
+        _ => false,
+    }
+}
+
+fn find_json_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut dir_entries = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    dir_entries.sort();
+    for path in dir_entries {
+        if path.is_dir() {
+            find_json_files(&path, files);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            files.push(path);
+        }
+    }
+}
+
+fn index_entry(
+    results_dir: &Path,
+    path: &Path,
+    html_file: &Path,
+    index_dir: &Path,
+    report: &Report,
+) -> IndexEntry {
+    let environment = report.environment.as_ref();
+    let (date, date_from_file) = match environment {
+        Some(environment) => (environment.started_at.clone(), false),
+        None => (
+            format_utc(std::fs::metadata(path).unwrap().modified().unwrap()),
+            true,
+        ),
+    };
+    let mut best = Vec::<MethodBest>::new();
+    for item in &report.items {
+        let method = item.method.name();
+        let index = match best.iter().position(|best| best.method == method) {
+            Some(index) => index,
+            None => {
+                best.push(MethodBest {
+                    method,
+                    write_mbps: 0.0,
+                    read_mbps: 0.0,
+                });
+                best.len() - 1
+            }
+        };
+        best[index].write_mbps = best[index].write_mbps.max(item.write_tput_mbps);
+        best[index].read_mbps = best[index].read_mbps.max(item.read_tput_mbps);
+    }
+    best.sort_by_key(|best| best.method);
+    IndexEntry {
+        label: relative(&path.with_extension(""), results_dir),
+        report_href: relative(html_file, index_dir),
+        date,
+        date_from_file,
+        hostname: environment.map(|environment| environment.hostname.clone()),
+        kernel: environment.map(|environment| environment.kernel.clone()),
+        devices: environment
+            .map(|environment| {
+                environment
+                    .targets
+                    .iter()
+                    .map(|target| {
+                        let details = [target.model.as_deref(), target.filesystem.as_deref()]
+                            .into_iter()
+                            .flatten()
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!(
+                            "{label}{device}{details}",
+                            label = target
+                                .label
+                                .as_ref()
+                                .map_or(String::new(), |label| format!("{label}: ")),
+                            device = target.device.as_deref().unwrap_or("?"),
+                            details = if details.is_empty() {
+                                details
+                            } else {
+                                format!(" ({details})")
+                            },
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        best,
+    }
+}
+
+fn relative(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
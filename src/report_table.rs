@@ -0,0 +1,442 @@
+use std::fmt::Write;
+
+use serde::{Serialize, Serializer, ser::SerializeMap};
+use serde_json::{Map, Value};
+
+use crate::{ReportItem, pivot::Pivot};
+
+// The main report table with the method settings flattened into one column per parameter, for the
+// non-HTML report formats. Parameters a method doesn't have are null.
+pub struct ReportTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    // (row, column) of cells to emphasize, such as pivot winners
+    pub highlighted: Vec<(usize, usize)>,
+}
+
+impl ReportTable {
+    pub fn new(items: &[ReportItem]) -> ReportTable {
+        let methods = items
+            .iter()
+            .map(|item| match serde_json::to_value(&item.method).unwrap() {
+                Value::Object(method) => flatten(method),
+                method => panic!("unexpected method {method}"),
+            })
+            .collect::<Vec<_>>();
+        let mut params = Vec::<String>::new();
+        for method in &methods {
+            for param in method.keys() {
+                if param != "type" && !params.contains(param) {
+                    params.push(param.clone());
+                }
+            }
+        }
+        // only merged reports have machines
+        let merged = items.iter().any(|item| item.machine.is_some());
+        let mut columns = Vec::new();
+        if merged {
+            columns.push("machine".to_string());
+        }
+        columns.extend(["target".to_string(), "method".to_string()]);
+        columns.extend(params.iter().cloned());
+        columns.extend(
+            [
+                "sequence",
+                "preallocate",
+                "file_layout",
+                "files",
+                "write_mbps",
+                "overwrite_mbps",
+                "read_mbps",
+                "write_cpu_secs_per_gib",
+                "overwrite_cpu_secs_per_gib",
+                "read_cpu_secs_per_gib",
+            ]
+            .map(String::from),
+        );
+        let rows = items
+            .iter()
+            .zip(&methods)
+            .map(|(item, method)| {
+                let mut row = row(item, method, &params);
+                if merged {
+                    row.insert(0, Value::from(item.machine.clone()));
+                }
+                row
+            })
+            .collect();
+        ReportTable {
+            columns,
+            rows,
+            highlighted: Vec::new(),
+        }
+    }
+
+    pub fn from_pivot(pivot: &Pivot) -> ReportTable {
+        let mut columns = vec![pivot.row_axis.to_string()];
+        columns.extend(pivot.columns.iter().cloned());
+        ReportTable {
+            columns,
+            rows: pivot
+                .rows
+                .iter()
+                .map(|row| {
+                    std::iter::once(Value::from(row.key.clone()))
+                        .chain(row.cells.iter().map(|&cell| Value::from(cell)))
+                        .collect()
+                })
+                .collect(),
+            highlighted: pivot
+                .rows
+                .iter()
+                .enumerate()
+                .filter_map(|(i, row)| Some((i, row.winner? + 1)))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let objects = self
+            .rows
+            .iter()
+            .map(|row| JsonRow {
+                columns: &self.columns,
+                row,
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string_pretty(&objects).unwrap()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        writeln!(
+            csv,
+            "{}",
+            self.columns
+                .iter()
+                .map(|column| csv_field(column))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+        .unwrap();
+        for row in &self.rows {
+            writeln!(
+                csv,
+                "{}",
+                row.iter()
+                    .map(|value| csv_field(&cell(value, false)))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+        writeln!(
+            markdown,
+            "| {} |",
+            self.columns
+                .iter()
+                .map(|column| markdown_cell(column))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        )
+        .unwrap();
+        writeln!(
+            markdown,
+            "|{}|",
+            self.numeric_columns()
+                .iter()
+                .map(|&numeric| if numeric { "--:" } else { "---" })
+                .collect::<Vec<_>>()
+                .join("|")
+        )
+        .unwrap();
+        for (i, row) in self.rows.iter().enumerate() {
+            writeln!(
+                markdown,
+                "| {} |",
+                row.iter()
+                    .enumerate()
+                    .map(|(j, value)| {
+                        let cell = markdown_cell(&cell(value, true));
+                        if self.highlighted.contains(&(i, j)) {
+                            format!("**{cell}**")
+                        } else {
+                            cell
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            )
+            .unwrap();
+        }
+        markdown
+    }
+
+    // Aligned columns for the terminal, numbers right-aligned and highlighted cells marked with `*`.
+    pub fn to_text_table(&self) -> String {
+        let marker_width = if self.highlighted.is_empty() { 0 } else { 1 };
+        let cells = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(|(j, value)| {
+                        let marker = if self.highlighted.contains(&(i, j)) {
+                            "*"
+                        } else {
+                            ""
+                        };
+                        format!("{cell}{marker:<marker_width$}", cell = cell(value, true))
+                    })
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        let widths = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].len())
+                    .fold(column.len(), usize::max)
+            })
+            .collect::<Vec<_>>();
+        let numeric = self.numeric_columns();
+        let mut table = String::new();
+        for row in std::iter::once(&self.columns).chain(&cells) {
+            let line = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    if numeric[i] {
+                        format!("{cell:>width$}", width = widths[i])
+                    } else {
+                        format!("{cell:<width$}", width = widths[i])
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(table, "{}", line.trim_end()).unwrap();
+        }
+        table
+    }
+
+    fn numeric_columns(&self) -> Vec<bool> {
+        (0..self.columns.len())
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .all(|row| row[i].is_number() || row[i].is_null())
+                    && self.rows.iter().any(|row| row[i].is_number())
+            })
+            .collect()
+    }
+}
+
+// serde_json::Map sorts its keys, this keeps the column order
+struct JsonRow<'a> {
+    columns: &'a [String],
+    row: &'a [Value],
+}
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, value) in self.columns.iter().zip(self.row) {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+}
+
+fn row(item: &ReportItem, method: &Map<String, Value>, params: &[String]) -> Vec<Value> {
+    let mut row = vec![Value::from(item.target.clone()), method["type"].clone()];
+    row.extend(
+        params
+            .iter()
+            .map(|param| method.get(param).cloned().unwrap_or(Value::Null)),
+    );
+    let layout = serde_json::to_value(item.file_layout).unwrap();
+    row.extend([
+        serde_json::to_value(item.sequence).unwrap(),
+        serde_json::to_value(item.preallocate).unwrap(),
+        layout["type"].clone(),
+        layout.get("files").cloned().unwrap_or(Value::Null),
+        Value::from(item.write_tput_mbps),
+        Value::from(item.overwrite_tput_mbps),
+        Value::from(item.read_tput_mbps),
+    ]);
+    row.extend(
+        [&item.write_cpu, &item.overwrite_cpu, &item.read_cpu]
+            .map(|cpu| Value::from(cpu.as_ref().map(|cpu| cpu.cpu_secs_per_gib))),
+    );
+    row
+}
+
+// Nested settings such as `vectored` get one column per field too.
+fn flatten(method: Map<String, Value>) -> Map<String, Value> {
+    let mut flat = Map::new();
+    for (param, value) in method {
+        match value {
+            Value::Object(fields) => flat.extend(fields),
+            value => {
+                flat.insert(param, value);
+            }
+        }
+    }
+    flat
+}
+
+fn cell(value: &Value, round: bool) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) if round && n.is_f64() => format!("{:.2}", n.as_f64().unwrap()),
+        // method flags, e.g. `nowait+hipri`
+        Value::Array(values) => values
+            .iter()
+            .map(|value| cell(value, round))
+            .collect::<Vec<_>>()
+            .join("+"),
+        value => value.to_string(),
+    }
+}
+
+fn markdown_cell(cell: &str) -> String {
+    cell.replace('|', "\\|")
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        IoSequence,
+        bench_settings::{FileLayout, IoMethodSettings, Preallocate},
+        buffered_io::Buffered,
+        direct_io_uring::DirectUring,
+        io_data::Vectored,
+        rw_flags::RwFlag,
+    };
+
+    fn item(target: &str, method: IoMethodSettings, read_tput_mbps: f64) -> ReportItem {
+        let mut item = ReportItem::new(
+            method,
+            IoSequence::Random,
+            Some(Preallocate::Sparse),
+            FileLayout::RandomFile { files: 4 },
+        );
+        item.target = Some(target.to_string());
+        item.read_tput_mbps = read_tput_mbps;
+        item
+    }
+
+    fn items() -> Vec<ReportItem> {
+        vec![
+            item(
+                "nvme, \"fast\"",
+                IoMethodSettings::Buffered(Buffered {
+                    block_size: 4096,
+                    vectored: None,
+                    rw_flags: vec![RwFlag::Nowait, RwFlag::Hipri],
+                }),
+                1000.126,
+            ),
+            item(
+                "sata|slow",
+                IoMethodSettings::DirectUring(DirectUring {
+                    block_size: 65536,
+                    concurrency: 32,
+                    vectored: Some(Vectored {
+                        iovecs: 4,
+                        segment_size: 16384,
+                    }),
+                }),
+                500.0,
+            ),
+        ]
+    }
+
+    #[test]
+    fn one_column_per_parameter() {
+        let table = ReportTable::new(&items());
+        assert_eq!(
+            table.columns[..8],
+            [
+                "target",
+                "method",
+                "block_size",
+                "rw_flags",
+                "concurrency",
+                "iovecs",
+                "segment_size",
+                "sequence"
+            ]
+        );
+        let params = |row: &[Value]| row[1..7].to_vec();
+        assert_eq!(
+            params(&table.rows[0]),
+            [
+                Value::from("buffered"),
+                Value::from(4096),
+                serde_json::json!(["nowait", "hipri"]),
+                Value::Null,
+                Value::Null,
+                Value::Null
+            ]
+        );
+        assert_eq!(
+            params(&table.rows[1]),
+            [
+                Value::from("direct_uring"),
+                Value::from(65536),
+                Value::Null,
+                Value::from(32),
+                Value::from(4),
+                Value::from(16384)
+            ]
+        );
+        let json: Value = serde_json::from_str(&table.to_json()).unwrap();
+        assert_eq!(json[1]["segment_size"], 16384);
+        assert_eq!(json[1]["files"], 4);
+    }
+
+    #[test]
+    fn csv_escaping() {
+        let csv = ReportTable::new(&items()).to_csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert!(
+            lines[1].starts_with("\"nvme, \"\"fast\"\"\",buffered,4096,nowait+hipri,,,,Random,")
+        );
+        assert!(lines[2].starts_with("sata|slow,direct_uring,65536,,32,4,16384,Random,"));
+        assert_eq!(csv_field("a\nb"), "\"a\nb\"");
+    }
+
+    #[test]
+    fn markdown_escaping() {
+        let mut table = ReportTable::new(&items());
+        table.columns[0] = "target|label".to_string();
+        let markdown = table.to_markdown();
+        let lines = markdown.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("| target\\|label | method | block_size |"));
+        assert!(lines[1].starts_with("|---|---|--:|---|--:|"));
+        assert!(lines[2].starts_with("| nvme, \"fast\" | buffered | 4096 | nowait+hipri |  |"));
+        assert!(lines[3].starts_with("| sata\\|slow | direct_uring | 65536 |  | 32 | 4 | 16384 |"));
+        assert!(lines[2].contains("| 1000.13 |"));
+    }
+}
@@ -1,13 +1,34 @@
 use std::{
-    fs::File,
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
     io::ErrorKind,
+    os::{
+        fd::AsRawFd,
+        unix::fs::{FileExt, OpenOptionsExt},
+    },
     path::{Path, PathBuf},
+    str::FromStr,
     time::{Duration, Instant},
 };
 
 use crate::{
-    IoMethod, IoSequence, ReportItem,
-    bench_settings::{IoMethodSettings, read_bench_settings},
+    IoMethod, IoOp, IoSequence, Report, ReportItem,
+    assertions::{Assertion, check_assertions, read_assertions},
+    bench_settings::{BenchSettings, IoMethodSettings, Preallocate, read_bench_settings},
+    block_device::{self, block_device_info, is_block_device},
+    cpu_usage::{CpuSnapshot, CpuUsage},
+    disk_stats::{BlockDevice, DiskSnapshot, DiskStats, backing_device},
+    io_data::{SegmentedBuf, Vectored, Workload},
+    latency::LatencySummary,
+    openmetrics::{openmetrics, serve},
+    pacing::{OpenLoopPoint, OpenLoopSettings, Pacer},
+    page_cache::{PageCacheStats, ReadaheadOverride},
+    replay::run_replay,
+    run_environment::RunEnvironment,
+    rw_flags::{RwFlag, probe, take_nowait_fallbacks},
+    sampler::{Sampler, ThroughputSample},
+    small_files::run_small_files,
+    steady_state::{SteadyStateSettings, measure_steady_state},
 };
 
 #[derive(Debug, Clone, clap::Args)]
@@ -16,119 +37,641 @@ pub struct RunCommand {
     pub settings_file: PathBuf,
     #[clap(long, value_parser, default_value = "target/test_file")]
     pub test_file: PathBuf,
+    #[clap(long, value_parser, default_value = "target/test_dir")]
+    pub test_dir: PathBuf,
+    /// LABEL=PATH, may be repeated; overrides --test-file and --test-dir
+    #[clap(long = "target", value_parser)]
+    pub targets: Vec<Target>,
+    /// Benchmark raw block devices instead of regular files
+    #[clap(long)]
+    pub block_device: bool,
+    /// Confirm that the contents of the block devices may be overwritten
+    #[clap(long)]
+    pub destroy_data: bool,
     #[clap(long, value_parser, default_value = "target/report.json")]
     pub report_file: PathBuf,
+    /// Name of this machine when reports are merged, the hostname by default
+    #[clap(long)]
+    pub machine: Option<String>,
+    /// Also write the results as OpenMetrics text
+    #[clap(long, value_parser)]
+    pub openmetrics_file: Option<PathBuf>,
+    /// After the run, serve the results as OpenMetrics at http://ADDR/metrics until interrupted;
+    /// not allowed with assertions, as the exit status would never be set
+    #[clap(long, value_name = "ADDR")]
+    pub serve: Option<String>,
+    /// JSON list of performance budgets, checked along with the ones in the settings file
+    #[clap(long, value_parser)]
+    pub assertions_file: Option<PathBuf>,
+    /// Write the assertion results as JUnit XML
+    #[clap(long, value_parser)]
+    pub junit_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub label: Option<String>,
+    pub test_file: PathBuf,
+    pub test_dir: PathBuf,
+    pub block_device: bool,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected LABEL=PATH, got {s:?}"))?;
+        if label.is_empty() {
+            return Err(format!("empty label in {s:?}, expected LABEL=PATH"));
+        }
+        let test_file = PathBuf::from(path);
+        let mut test_dir = test_file.as_os_str().to_owned();
+        test_dir.push(".dir");
+        Ok(Target {
+            label: Some(label.to_string()),
+            test_file,
+            test_dir: test_dir.into(),
+            block_device: false,
+        })
+    }
+}
+
+impl RunCommand {
+    fn targets(&self) -> Vec<Target> {
+        let mut targets = if self.targets.is_empty() {
+            vec![Target {
+                label: None,
+                test_file: self.test_file.clone(),
+                test_dir: self.test_dir.clone(),
+                block_device: false,
+            }]
+        } else {
+            self.targets.clone()
+        };
+        for target in &mut targets {
+            target.block_device = self.block_device;
+        }
+        targets
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WriteMode {
+    Fresh,
+    Overwrite,
 }
 
 pub fn run_benchmark(run_command: &RunCommand) {
-    let settings = read_bench_settings(run_command);
-    let mut report_items = Vec::<ReportItem>::new();
+    let mut settings = read_bench_settings(&run_command.settings_file);
+    if let Some(assertions_file) = &run_command.assertions_file {
+        settings.assertions.extend(read_assertions(assertions_file));
+    }
+    if serve_hides_failures(run_command.serve.as_deref(), &settings.assertions) {
+        eprintln!(
+            "--serve does not return, failed assertions could not set the exit status; \
+             use --openmetrics-file with assertions"
+        );
+        std::process::exit(2);
+    }
+    // a latency budget can only be checked with the latency pass
+    settings.measure_latency |= settings
+        .assertions
+        .iter()
+        .any(|assertion| assertion.max_p99_us.is_some());
+    let mut report = Report::default();
+    let targets = run_command.targets();
+    check_targets(run_command, &targets, &settings);
+    report.environment = Some(RunEnvironment::now(
+        run_command.machine.as_deref(),
+        targets
+            .iter()
+            .map(|target| (target.label.as_deref(), target.test_file.as_path())),
+    ));
     drop_caches();
-    for m in &settings.methods {
-        for sequence in [
-            IoSequence::Sequential,
-            IoSequence::Random,
-            // IoSequence::Sequential,
-        ] {
-            let path = &run_command.test_file;
-            let write_duration =
-                measure_write_file(path, settings.file_size, m, IoSequence::Sequential);
-            let write_tput_mbps =
-                settings.file_size as f64 / 1024.0 / 1024.0 / write_duration.as_secs_f64();
-            println!(
-                "write {m:?} {sequence:?} => {d:.3} sec {write_tput_mbps:.2} MiB/sec",
-                d = write_duration.as_secs_f64()
-            );
-            let read_duration =
-                measure_read_file(path, settings.file_size, m, IoSequence::Sequential);
-            let read_tput_mbps =
-                settings.file_size as f64 / 1024.0 / 1024.0 / read_duration.as_secs_f64();
-            println!(
-                "read {m:?} {sequence:?} => {d:.3} sec {read_tput_mbps:.2} MiB/sec",
-                d = read_duration.as_secs_f64()
-            );
-            report_items.push(ReportItem {
-                method: m.clone(),
-                sequence,
-                write_tput_mbps,
-                read_tput_mbps,
-            });
-            remove_file_maybe(path);
+    for target in targets {
+        if let Some(label) = &target.label {
+            println!("target {label}: {path}", path = target.test_file.display());
         }
+        run_target(&settings, &target, &mut report);
     }
 
     std::fs::write(
         &run_command.report_file,
-        serde_json::to_string(&report_items).unwrap(),
+        serde_json::to_string(&report).unwrap(),
     )
     .unwrap();
+
+    let passed = settings.assertions.is_empty()
+        || check_assertions(
+            &report,
+            &settings.assertions,
+            run_command.junit_file.as_deref(),
+        );
+    if run_command.openmetrics_file.is_some() || run_command.serve.is_some() {
+        let metrics = openmetrics(&report);
+        if let Some(openmetrics_file) = &run_command.openmetrics_file {
+            std::fs::write(openmetrics_file, &metrics).unwrap();
+        }
+        if let Some(addr) = &run_command.serve {
+            serve(addr, &metrics);
+        }
+    }
+    if !passed {
+        std::process::exit(1);
+    }
 }
 
-fn measure_write_file(
-    path: &Path,
-    file_size: u64,
-    io_method: &IoMethodSettings,
-    sequence: IoSequence,
-) -> Duration {
-    remove_file_maybe(path);
-    let file = File::create_new(path).unwrap();
-    file.set_len(file_size).unwrap();
-    file.sync_all().unwrap();
-    drop(file);
-    let start = Instant::now();
-    let mut iters = 0;
-    while iters <= 10 && start.elapsed() < Duration::from_secs(3) {
-        match io_method {
-            IoMethodSettings::Buffered(buffered) => buffered.write_file(path, file_size, sequence),
-            IoMethodSettings::BufferedUring(buffered_uring) => {
-                buffered_uring.write_file(path, file_size, sequence)
+// The server runs until interrupted, so a run with budgets would never exit with their result.
+fn serve_hides_failures(serve: Option<&str>, assertions: &[Assertion]) -> bool {
+    serve.is_some() && !assertions.is_empty()
+}
+
+fn check_targets(run_command: &RunCommand, targets: &[Target], settings: &BenchSettings) {
+    for target in targets {
+        let path = &target.test_file;
+        let is_block_device = is_block_device(path);
+        if is_block_device && !target.block_device {
+            eprintln!(
+                "{path} is a block device, pass --block-device to benchmark it",
+                path = path.display()
+            );
+            std::process::exit(2);
+        }
+        if !target.block_device {
+            continue;
+        }
+        if !is_block_device {
+            eprintln!("{path} is not a block device", path = path.display());
+            std::process::exit(2);
+        }
+        if !run_command.destroy_data {
+            eprintln!(
+                "refusing to overwrite {path} without --destroy-data",
+                path = path.display()
+            );
+            std::process::exit(2);
+        }
+        block_device::check_settings(path, &block_device_info(path), settings);
+    }
+}
+
+fn run_target(settings: &BenchSettings, target: &Target, report: &mut Report) {
+    let mut used_paths = BTreeSet::new();
+    let readahead = settings.readahead_kb.map(|readahead_kb| {
+        ReadaheadOverride::new(backing_device(&target.test_file), readahead_kb)
+    });
+    let fadvise = readahead.as_ref().and_then(|readahead| readahead.fadvise);
+    let sampler = Sampler::new(Duration::from_millis(settings.sample_interval_ms));
+    // block devices are used as they are: no preallocation and no removal
+    let preallocates = if target.block_device {
+        vec![None]
+    } else {
+        settings.preallocate.iter().copied().map(Some).collect()
+    };
+    for m in &settings.methods {
+        for &preallocate in &preallocates {
+            for &file_layout in &settings.file_layouts {
+                let file_count = file_layout.file_count(m.concurrency());
+                assert_eq!(settings.file_size % file_count as u64, 0);
+                let paths = test_file_paths(&target.test_file, file_count);
+                used_paths.extend(paths.iter().cloned());
+                for sequence in [
+                    IoSequence::Sequential,
+                    IoSequence::Random,
+                    // IoSequence::Sequential,
+                ] {
+                    let workload = Workload {
+                        paths: &paths,
+                        file_size: settings.file_size / file_count as u64,
+                        sequence,
+                        layout: file_layout,
+                        pacer: None,
+                        fadvise,
+                        sampler: Some(&sampler),
+                    };
+                    let mut item = measure_item(
+                        &workload,
+                        m,
+                        preallocate,
+                        settings.open_loop.as_ref(),
+                        settings.steady_state.as_ref(),
+                        settings.measure_latency,
+                    );
+                    item.target = target.label.clone();
+                    report.items.push(item);
+                    if preallocate.is_some_and(|p| p != Preallocate::ReuseExisting) {
+                        remove_files(&paths);
+                    }
+                }
             }
-            IoMethodSettings::Direct(direct) => direct.write_file(path, file_size, sequence),
-            IoMethodSettings::DirectAsync(direct_async) => {
-                direct_async.write_file(path, file_size, sequence)
+        }
+    }
+    for r in &settings.replay {
+        for m in &r.methods {
+            if !target.block_device {
+                prepare_file(&target.test_file, settings.file_size, Preallocate::ZeroFill);
+                used_paths.insert(target.test_file.clone());
             }
-            IoMethodSettings::DirectUring(direct_uring) => {
-                direct_uring.write_file(path, file_size, sequence)
+            drop_caches();
+            let mut item = run_replay(&target.test_file, settings.file_size, fadvise, r, m);
+            println!(
+                "replay {trace_file} {timing:?} {m:?} => {ops} ops in {d:.3} sec, {iops:.0} IOPS {tput_mbps:.2} MiB/sec",
+                trace_file = r.trace_file.display(),
+                timing = r.timing,
+                ops = item.ops,
+                d = item.duration_secs,
+                iops = item.iops,
+                tput_mbps = item.tput_mbps,
+            );
+            item.target = target.label.clone();
+            report.replay.push(item);
+        }
+    }
+    if target.block_device {
+        if !settings.small_files.is_empty() {
+            println!("skipping small files workloads on a block device");
+        }
+        return;
+    }
+    remove_files(&used_paths.into_iter().collect::<Vec<_>>());
+
+    for s in &settings.small_files {
+        let mut item = run_small_files(&target.test_dir, s);
+        for phase in &item.phases {
+            println!(
+                "small files {s:?} {phase:?} => {files_per_sec:.0} files/sec",
+                phase = phase.phase,
+                files_per_sec = phase.files_per_sec
+            );
+        }
+        item.target = target.label.clone();
+        report.small_files.push(item);
+    }
+}
+
+fn test_file_paths(test_file: &Path, file_count: u32) -> Vec<PathBuf> {
+    if file_count == 1 {
+        return vec![test_file.to_path_buf()];
+    }
+    (0..file_count)
+        .map(|file_idx| {
+            let mut path = test_file.as_os_str().to_owned();
+            path.push(format!(".{file_idx}"));
+            PathBuf::from(path)
+        })
+        .collect()
+}
+
+fn measure_item(
+    workload: &Workload,
+    m: &IoMethodSettings,
+    preallocate: Option<Preallocate>,
+    open_loop: Option<&OpenLoopSettings>,
+    steady_state: Option<&SteadyStateSettings>,
+    latency: bool,
+) -> ReportItem {
+    let sequence = workload.sequence;
+    let file_layout = workload.layout;
+    let total_size = workload.total_size();
+    probe_rw_flags(workload, m, preallocate);
+    let write = measure_write_file(workload, m, preallocate, WriteMode::Fresh);
+    let write_tput_mbps = total_size as f64 / 1024.0 / 1024.0 / write.duration.as_secs_f64();
+    println!(
+        "write {m:?} {sequence:?} {preallocate:?} {file_layout:?} => {d:.3} sec {write_tput_mbps:.2} MiB/sec, {cpu:.3} CPU sec/GiB",
+        d = write.duration.as_secs_f64(),
+        cpu = write.cpu.cpu_secs_per_gib,
+    );
+    let overwrite = measure_write_file(workload, m, preallocate, WriteMode::Overwrite);
+    let overwrite_tput_mbps =
+        total_size as f64 / 1024.0 / 1024.0 / overwrite.duration.as_secs_f64();
+    println!(
+        "overwrite {m:?} {sequence:?} {preallocate:?} {file_layout:?} => {d:.3} sec {overwrite_tput_mbps:.2} MiB/sec, {cpu:.3} CPU sec/GiB",
+        d = overwrite.duration.as_secs_f64(),
+        cpu = overwrite.cpu.cpu_secs_per_gib,
+    );
+    let read = measure_read_file(workload, m);
+    let read_tput_mbps = total_size as f64 / 1024.0 / 1024.0 / read.duration.as_secs_f64();
+    println!(
+        "read {m:?} {sequence:?} {preallocate:?} {file_layout:?} => {d:.3} sec {read_tput_mbps:.2} MiB/sec, {cpu:.3} CPU sec/GiB",
+        d = read.duration.as_secs_f64(),
+        cpu = read.cpu.cpu_secs_per_gib,
+    );
+    let (write_latency, read_latency) = if latency {
+        let (write_latency, read_latency) = measure_latency(workload, m);
+        (Some(write_latency), Some(read_latency))
+    } else {
+        (None, None)
+    };
+    let open_loop = match open_loop {
+        Some(open_loop) => measure_open_loop(workload, m, open_loop),
+        None => Vec::new(),
+    };
+    let steady_state = match steady_state {
+        Some(steady_state) => measure_steady_state(workload, m, steady_state),
+        None => Vec::new(),
+    };
+    let nowait = m.rw_flags().contains(&RwFlag::Nowait);
+    if nowait {
+        println!(
+            "RWF_NOWAIT fallbacks per pass => write {write:.0}, overwrite {overwrite:.0}, read {read:.0}",
+            write = write.nowait_fallbacks,
+            overwrite = overwrite.nowait_fallbacks,
+            read = read.nowait_fallbacks,
+        );
+    }
+    ReportItem {
+        machine: None,
+        target: None,
+        method: m.clone(),
+        sequence,
+        preallocate,
+        file_layout,
+        write_tput_mbps,
+        overwrite_tput_mbps: Some(overwrite_tput_mbps),
+        read_tput_mbps,
+        open_loop,
+        write_latency,
+        read_latency,
+        write_cpu: Some(write.cpu),
+        overwrite_cpu: Some(overwrite.cpu),
+        read_cpu: Some(read.cpu),
+        write_disk: write.disk,
+        overwrite_disk: overwrite.disk,
+        read_disk: read.disk,
+        write_page_cache: write.page_cache,
+        overwrite_page_cache: overwrite.page_cache,
+        read_page_cache: read.page_cache,
+        write_samples: write.samples,
+        overwrite_samples: overwrite.samples,
+        read_samples: read.samples,
+        write_pass_mbps: write.pass_mbps,
+        overwrite_pass_mbps: overwrite.pass_mbps,
+        read_pass_mbps: read.pass_mbps,
+        write_nowait_fallbacks: nowait.then_some(write.nowait_fallbacks),
+        overwrite_nowait_fallbacks: nowait.then_some(overwrite.nowait_fallbacks),
+        read_nowait_fallbacks: nowait.then_some(read.nowait_fallbacks),
+        steady_state,
+    }
+}
+
+// Finds the preadv2/pwritev2 flags the target doesn't support before anything is measured, on
+// the first test file, which is prepared first if it isn't there yet.
+fn probe_rw_flags(workload: &Workload, m: &IoMethodSettings, preallocate: Option<Preallocate>) {
+    if m.rw_flags().is_empty() {
+        return;
+    }
+    let block_size = m.block_size();
+    let path = &workload.paths[0];
+    // a block device has no length here, but without preallocation it's left alone
+    if !std::fs::metadata(path).is_ok_and(|metadata| metadata.len() >= block_size as u64) {
+        prepare_files(&workload.paths[..1], workload.file_size, preallocate);
+    }
+    let direct = matches!(m, IoMethodSettings::Direct(_));
+    let file = OpenOptions::new()
+        .write(true)
+        .read(true)
+        .custom_flags(if direct { libc::O_DIRECT } else { 0 })
+        .open(path)
+        .unwrap();
+    let buf = SegmentedBuf::new(
+        m.vectored().unwrap_or(Vectored::contiguous(block_size)),
+        block_size,
+        true,
+    );
+    probe(&file, buf.iovecs(), m.rw_flags());
+}
+
+// Averages over all passes of a measurement.
+struct Measurement {
+    duration: Duration,
+    cpu: CpuUsage,
+    disk: Option<DiskStats>,
+    page_cache: Option<PageCacheStats>,
+    samples: Vec<ThroughputSample>,
+    pass_mbps: Vec<f64>,
+    nowait_fallbacks: f64,
+}
+
+#[derive(Default)]
+struct MeasurementTotals {
+    iters: u32,
+    duration: Duration,
+    pass_durations: Vec<Duration>,
+    cpu: CpuUsage,
+    disk: Option<DiskStats>,
+    page_cache: Option<PageCacheStats>,
+    // throughput during the last pass
+    samples: Vec<ThroughputSample>,
+    nowait_fallbacks: u64,
+}
+
+impl MeasurementTotals {
+    fn measure_pass(
+        &mut self,
+        workload: &Workload,
+        device: Option<BlockDevice>,
+        pass: impl FnOnce(),
+    ) {
+        let disk_start = DiskSnapshot::now(device);
+        let cpu_start = CpuSnapshot::now();
+        take_nowait_fallbacks();
+        let start = Instant::now();
+        self.samples = match workload.sampler {
+            Some(sampler) => sampler.sample_during(pass),
+            None => {
+                pass();
+                Vec::new()
             }
+        };
+        let elapsed = start.elapsed();
+        self.duration += elapsed;
+        self.pass_durations.push(elapsed);
+        self.nowait_fallbacks += take_nowait_fallbacks();
+        self.cpu.add(&CpuSnapshot::now().usage_since(&cpu_start));
+        if let (Some(disk_start), Some(disk_end)) = (disk_start, DiskSnapshot::now(device)) {
+            self.disk
+                .get_or_insert_default()
+                .add(&disk_end.stats_since(&disk_start));
         }
-        iters += 1;
+        self.page_cache = Some(PageCacheStats::now(workload, device));
+        self.iters += 1;
+    }
+
+    fn done(&self) -> bool {
+        self.iters > 10 || self.duration >= Duration::from_secs(3)
     }
 
-    start.elapsed() / iters
+    fn finish(self, bytes_per_pass: u64) -> Measurement {
+        Measurement {
+            duration: self.duration / self.iters,
+            cpu: self.cpu.per_pass(self.iters, bytes_per_pass),
+            disk: self.disk.map(|disk| disk.per_pass(self.iters)),
+            page_cache: self.page_cache,
+            samples: self.samples,
+            pass_mbps: self
+                .pass_durations
+                .iter()
+                .map(|duration| bytes_per_pass as f64 / 1024.0 / 1024.0 / duration.as_secs_f64())
+                .collect(),
+            nowait_fallbacks: self.nowait_fallbacks as f64 / self.iters as f64,
+        }
+    }
 }
 
-fn measure_read_file(
-    path: &Path,
-    file_size: u64,
+fn measure_write_file(
+    workload: &Workload,
     io_method: &IoMethodSettings,
-    sequence: IoSequence,
-) -> Duration {
-    let mut iters = 0;
-    let mut duration = Duration::ZERO;
-    while iters <= 10 && duration < Duration::from_secs(3) {
+    preallocate: Option<Preallocate>,
+    write_mode: WriteMode,
+) -> Measurement {
+    if write_mode == WriteMode::Overwrite {
+        prepare_files(workload.paths, workload.file_size, preallocate);
+        io_method.write_file(workload);
+    }
+    let device = backing_device(&workload.paths[0]);
+    let mut totals = MeasurementTotals::default();
+    while !totals.done() {
+        if write_mode == WriteMode::Fresh {
+            prepare_files(workload.paths, workload.file_size, preallocate);
+        }
+        totals.measure_pass(workload, device, || io_method.write_file(workload));
+    }
+
+    totals.finish(workload.total_size())
+}
+
+fn measure_read_file(workload: &Workload, io_method: &IoMethodSettings) -> Measurement {
+    let device = backing_device(&workload.paths[0]);
+    let mut totals = MeasurementTotals::default();
+    while !totals.done() {
         drop_caches();
-        let start = Instant::now();
-        match io_method {
-            IoMethodSettings::Buffered(buffered) => buffered.read_file(path, file_size, sequence),
-            IoMethodSettings::BufferedUring(buffered_uring) => {
-                buffered_uring.read_file(path, file_size, sequence)
-            }
-            IoMethodSettings::Direct(direct) => direct.read_file(path, file_size, sequence),
-            IoMethodSettings::DirectAsync(direct_async) => {
-                direct_async.read_file(path, file_size, sequence)
-            }
-            IoMethodSettings::DirectUring(direct_uring) => {
-                direct_uring.read_file(path, file_size, sequence);
+        totals.measure_pass(workload, device, || io_method.read_file(workload));
+    }
+
+    totals.finish(workload.total_size())
+}
+
+// One more write and read pass with every operation timed. Kept apart from the throughput passes
+// since recording a latency takes the pacer's lock, which would slow down the fastest backends.
+// Like the open-loop runs, the writes overwrite the file left by the throughput passes.
+fn measure_latency(
+    workload: &Workload,
+    io_method: &IoMethodSettings,
+) -> (LatencySummary, LatencySummary) {
+    let pacer = Pacer::immediate(workload.total_size() / io_method.block_size() as u64);
+    let timed = Workload {
+        pacer: Some(&pacer),
+        sampler: None,
+        ..*workload
+    };
+    io_method.write_file(&timed);
+    drop_caches();
+    io_method.read_file(&timed);
+    let mut latencies = pacer.into_latencies();
+    let mut summary = |op| LatencySummary::from_samples(latencies.entry(op).or_default());
+    let (write, read) = (summary(IoOp::Write), summary(IoOp::Read));
+    println!(
+        "latency {io_method:?} {sequence:?} => write p50 {write_p50:.0} us, p99 {write_p99:.0} us, read p50 {read_p50:.0} us, p99 {read_p99:.0} us",
+        sequence = workload.sequence,
+        write_p50 = write.p50_us,
+        write_p99 = write.p99_us,
+        read_p50 = read.p50_us,
+        read_p99 = read.p99_us,
+    );
+    (write, read)
+}
+
+// Runs against the file left fully written by the closed-loop measurements, so open-loop writes
+// are overwrites.
+fn measure_open_loop(
+    workload: &Workload,
+    io_method: &IoMethodSettings,
+    open_loop: &OpenLoopSettings,
+) -> Vec<OpenLoopPoint> {
+    let mut points = Vec::new();
+    for op in [IoOp::Read, IoOp::Write] {
+        for rate in &open_loop.rates {
+            let target_iops = rate.iops(io_method.block_size());
+            let op_limit = (target_iops * open_loop.duration_secs).ceil() as u64;
+            let pacer = Pacer::new(target_iops, open_loop.arrivals, op_limit);
+            let paced = Workload {
+                pacer: Some(&pacer),
+                ..*workload
+            };
+            drop_caches();
+            let start = Instant::now();
+            match op {
+                IoOp::Read => io_method.read_file(&paced),
+                IoOp::Write => io_method.write_file(&paced),
             }
+            let elapsed = start.elapsed();
+            let mut latencies = pacer.into_latencies().remove(&op).unwrap_or_default();
+            let achieved_iops = latencies.len() as f64 / elapsed.as_secs_f64();
+            let latency = LatencySummary::from_samples(&mut latencies);
+            println!(
+                "open loop {op:?} {io_method:?} {sequence:?} {target_iops:.0} IOPS => {achieved_iops:.0} IOPS, p50 {p50:.0} us, p99 {p99:.0} us",
+                sequence = workload.sequence,
+                p50 = latency.p50_us,
+                p99 = latency.p99_us,
+            );
+            points.push(OpenLoopPoint {
+                op,
+                target_iops,
+                achieved_iops,
+                latency,
+            });
         }
-        duration += start.elapsed();
-        iters += 1;
     }
+    points
+}
+
+fn prepare_files(paths: &[PathBuf], file_size: u64, preallocate: Option<Preallocate>) {
+    let Some(preallocate) = preallocate else {
+        return;
+    };
+    for path in paths {
+        prepare_file(path, file_size, preallocate);
+    }
+}
+
+fn prepare_file(path: &Path, file_size: u64, preallocate: Preallocate) {
+    if preallocate == Preallocate::ReuseExisting
+        && std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == file_size)
+    {
+        return;
+    }
+    remove_file_maybe(path);
+    let file = File::create_new(path).unwrap();
+    match preallocate {
+        Preallocate::Sparse => file.set_len(file_size).unwrap(),
+        Preallocate::Fallocate => fallocate(&file, 0, file_size),
+        Preallocate::FallocateKeepSize => fallocate(&file, libc::FALLOC_FL_KEEP_SIZE, file_size),
+        Preallocate::ZeroFill | Preallocate::ReuseExisting => zero_fill(&file, file_size),
+    }
+    file.sync_all().unwrap();
+}
+
+fn fallocate(file: &File, mode: i32, len: u64) {
+    // SAFETY: fd is valid for the duration of the call
+    let rc = unsafe { libc::fallocate(file.as_raw_fd(), mode, 0, len as libc::off_t) };
+    if rc != 0 {
+        panic!("fallocate: {}", std::io::Error::last_os_error());
+    }
+}
 
-    duration / iters
+fn zero_fill(file: &File, file_size: u64) {
+    let buf = vec![0u8; 1024 * 1024];
+    let mut offset = 0;
+    while offset < file_size {
+        let len = (file_size - offset).min(buf.len() as u64) as usize;
+        file.write_all_at(&buf[..len], offset).unwrap();
+        offset += len as u64;
+    }
 }
 
-fn drop_caches() {
+pub(crate) fn drop_caches() {
     use std::process::Command;
     let rc = Command::new("sudo")
     .args(["sh", "-c", "sync && (echo 3 > /proc/sys/vm/drop_caches) && sync && (echo 3 > /proc/sys/vm/drop_caches)"])
@@ -136,6 +679,12 @@ fn drop_caches() {
     assert!(rc.success());
 }
 
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        remove_file_maybe(path);
+    }
+}
+
 fn remove_file_maybe(path: &Path) {
     match std::fs::remove_file(path) {
         Ok(()) => {}
@@ -143,3 +692,17 @@ fn remove_file_maybe(path: &Path) {
         Err(e) => panic!("error: {e}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serve_with_assertions() {
+        let assertions: Vec<Assertion> =
+            serde_json::from_str(include_str!("../assertions-lite.json")).unwrap();
+        assert!(serve_hides_failures(Some("127.0.0.1:9100"), &assertions));
+        assert!(!serve_hides_failures(Some("127.0.0.1:9100"), &[]));
+        assert!(!serve_hides_failures(None, &assertions));
+    }
+}
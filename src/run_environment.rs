@@ -0,0 +1,131 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::disk_stats::{BlockDevice, backing_device};
+
+// Where and when a report was produced, so reports can be told apart once they're collected in
+// results/.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunEnvironment {
+    // label for merged reports, the hostname unless given with `run --machine`
+    #[serde(default)]
+    pub machine: String,
+    pub hostname: String,
+    pub kernel: String,
+    // UTC, RFC 3339
+    pub started_at: String,
+    pub cpu_model: Option<String>,
+    pub cpus: usize,
+    pub memory_kib: u64,
+    pub targets: Vec<TargetEnvironment>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TargetEnvironment {
+    pub label: Option<String>,
+    pub path: PathBuf,
+    pub device: Option<String>,
+    pub model: Option<String>,
+    pub rotational: Option<bool>,
+    pub filesystem: Option<String>,
+}
+
+impl RunEnvironment {
+    pub fn now<'a>(
+        machine: Option<&str>,
+        targets: impl Iterator<Item = (Option<&'a str>, &'a Path)>,
+    ) -> RunEnvironment {
+        let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        let hostname = read_trimmed("/proc/sys/kernel/hostname").unwrap_or_default();
+        RunEnvironment {
+            machine: machine.map_or_else(|| hostname.clone(), String::from),
+            hostname,
+            kernel: read_trimmed("/proc/sys/kernel/osrelease").unwrap_or_default(),
+            started_at: format_utc(SystemTime::now()),
+            cpu_model: cpuinfo.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                (name.trim() == "model name").then(|| value.trim().to_string())
+            }),
+            cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+            memory_kib: meminfo
+                .lines()
+                .find_map(|line| {
+                    let value = line.strip_prefix("MemTotal:")?;
+                    value.trim().trim_end_matches("kB").trim().parse().ok()
+                })
+                .unwrap_or(0),
+            targets: targets
+                .map(|(label, path)| TargetEnvironment::new(label, path))
+                .collect(),
+        }
+    }
+}
+
+impl TargetEnvironment {
+    fn new(label: Option<&str>, path: &Path) -> TargetEnvironment {
+        let device = backing_device(path);
+        TargetEnvironment {
+            label: label.map(String::from),
+            path: path.to_path_buf(),
+            device: device.map(|device| device.name()),
+            model: device.and_then(|device| disk_attribute(device, "device/model")),
+            rotational: device
+                .and_then(|device| disk_attribute(device, "queue/rotational"))
+                .map(|rotational| rotational == "1"),
+            filesystem: device.and_then(filesystem),
+        }
+    }
+}
+
+// Partitions keep these attributes in the parent disk's directory.
+fn disk_attribute(device: BlockDevice, attribute: &str) -> Option<String> {
+    let sys_path = device.sys_path();
+    read_trimmed(sys_path.join(attribute))
+        .or_else(|| read_trimmed(sys_path.join("..").join(attribute)))
+}
+
+// The third field of mountinfo is MAJ:MIN, the filesystem type follows the " - " separator.
+fn filesystem(device: BlockDevice) -> Option<String> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    let dev = device.to_string();
+    mountinfo.lines().find_map(|line| {
+        let (mount, fs) = line.split_once(" - ")?;
+        if mount.split_whitespace().nth(2)? != dev {
+            return None;
+        }
+        Some(fs.split_whitespace().next()?.to_string())
+    })
+}
+
+fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+// Days to civil date from Howard Hinnant's date algorithms, to avoid a date crate for one timestamp.
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
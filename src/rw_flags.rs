@@ -0,0 +1,330 @@
+use std::{
+    fs::File,
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicI32, AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::IoOp;
+
+// Kernel 6.14's name for RWF_UNCACHED, newer than some libc releases we build with.
+const RWF_DONTCACHE: libc::c_int = 0x80;
+
+// Per-call flags of preadv2/pwritev2.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RwFlag {
+    // fail with EAGAIN instead of blocking, we count those and retry without the flag
+    Nowait,
+    // poll for the completion; only has an effect with O_DIRECT on a device with poll queues
+    Hipri,
+    // writes only, each write is durable like with O_DSYNC
+    Dsync,
+    // drop the pages from the page cache once the I/O is done; skipped where unsupported
+    Uncached,
+}
+
+static NOWAIT_FALLBACKS: AtomicU64 = AtomicU64::new(0);
+// flags the last `probe` found unsupported, by read and write, left out of the calls
+static UNSUPPORTED: [AtomicI32; 2] = [AtomicI32::new(0), AtomicI32::new(0)];
+
+// Flags a run can go on without; RWF_DSYNC changes what is measured, so it's never left out.
+const OPTIONAL: [(libc::c_int, &str); 3] = [
+    (libc::RWF_NOWAIT, "RWF_NOWAIT"),
+    (libc::RWF_HIPRI, "RWF_HIPRI"),
+    (RWF_DONTCACHE, "RWF_UNCACHED"),
+];
+
+impl RwFlag {
+    fn bits(self, op: IoOp) -> libc::c_int {
+        match (self, op) {
+            (RwFlag::Nowait, _) => libc::RWF_NOWAIT,
+            (RwFlag::Hipri, _) => libc::RWF_HIPRI,
+            (RwFlag::Dsync, IoOp::Write) => libc::RWF_DSYNC,
+            (RwFlag::Dsync, IoOp::Read) => 0,
+            (RwFlag::Uncached, _) => RWF_DONTCACHE,
+        }
+    }
+}
+
+fn flag_bits(flags: &[RwFlag], op: IoOp) -> libc::c_int {
+    flags.iter().fold(0, |bits, flag| bits | flag.bits(op))
+}
+
+fn unsupported(op: IoOp) -> &'static AtomicI32 {
+    &UNSUPPORTED[match op {
+        IoOp::Read => 0,
+        IoOp::Write => 1,
+    }]
+}
+
+// EAGAIN returns of RWF_NOWAIT calls since the last call, each followed by a blocking retry.
+pub fn take_nowait_fallbacks() -> u64 {
+    NOWAIT_FALLBACKS.swap(0, Ordering::Relaxed)
+}
+
+// Finds the optional flags that the kernel or the filesystem doesn't support for `file`, such as
+// RWF_UNCACHED before 6.14 or RWF_NOWAIT for buffered writes on some filesystems; they're
+// reported and left out of the following `transfer` calls. A flag only counts as unsupported if
+// the same call without it succeeds, any other failure panics. Writes and then reads `iovecs` at
+// offset 0, so it's called before the measured passes rather than inside them.
+pub fn probe(file: &File, iovecs: &[libc::iovec], flags: &[RwFlag]) {
+    for op in [IoOp::Write, IoOp::Read] {
+        let bits = flag_bits(flags, op);
+        let required = OPTIONAL.iter().fold(bits, |bits, (flag, _)| bits & !flag);
+        if let Err(error) = call(file, op, iovecs, 0, required) {
+            panic!("{op:?} at 0: {error}");
+        }
+        let mut rejected = 0;
+        for (flag, name) in OPTIONAL {
+            if bits & flag == 0 {
+                continue;
+            }
+            match call(file, op, iovecs, 0, required | flag) {
+                Ok(_) => {}
+                // supported, it just would have blocked
+                Err(error)
+                    if flag == libc::RWF_NOWAIT && error.raw_os_error() == Some(libc::EAGAIN) => {}
+                Err(error) => {
+                    println!("{name} isn't supported for {op:?} here: {error}, skipping it");
+                    rejected |= flag;
+                }
+            }
+        }
+        unsupported(op).store(rejected, Ordering::Relaxed);
+    }
+}
+
+// Transfers all of `iovecs` with preadv2/pwritev2, which without flags are preadv/pwritev.
+// RWF_NOWAIT can transfer part of the data before it would block, the rest is retried.
+pub fn transfer(file: &File, op: IoOp, iovecs: &[libc::iovec], offset: u64, flags: &[RwFlag]) {
+    let bits = flag_bits(flags, op) & !unsupported(op).load(Ordering::Relaxed);
+    transfer_with(op, iovecs, offset, bits, |iovecs, offset, bits| {
+        call(file, op, iovecs, offset, bits)
+    });
+}
+
+fn transfer_with(
+    op: IoOp,
+    iovecs: &[libc::iovec],
+    offset: u64,
+    bits: libc::c_int,
+    call: impl Fn(&[libc::iovec], u64, libc::c_int) -> std::io::Result<usize>,
+) {
+    let mut iovecs = iovecs.to_vec();
+    let mut offset = offset;
+    let mut bits = bits;
+    let mut remaining = iovecs.iter().map(|iovec| iovec.iov_len).sum::<usize>();
+    while remaining > 0 {
+        let mut n = match call(&iovecs, offset, bits) {
+            Ok(n) => n,
+            Err(error)
+                if error.raw_os_error() == Some(libc::EAGAIN) && bits & libc::RWF_NOWAIT != 0 =>
+            {
+                NOWAIT_FALLBACKS.fetch_add(1, Ordering::Relaxed);
+                bits &= !libc::RWF_NOWAIT;
+                continue;
+            }
+            Err(error) => panic!("{op:?} at {offset}: {error}"),
+        };
+        assert!(n > 0, "{op:?} at {offset}: unexpected end of file");
+        remaining -= n;
+        offset += n as u64;
+        // drop the iovecs that are done and advance into the first one that isn't
+        while n > 0 {
+            let len = iovecs[0].iov_len.min(n);
+            // SAFETY: len is within the iovec's buffer
+            iovecs[0].iov_base = unsafe { iovecs[0].iov_base.byte_add(len) };
+            iovecs[0].iov_len -= len;
+            n -= len;
+            if iovecs[0].iov_len == 0 {
+                iovecs.remove(0);
+            }
+        }
+    }
+}
+
+fn call(
+    file: &File,
+    op: IoOp,
+    iovecs: &[libc::iovec],
+    offset: u64,
+    bits: libc::c_int,
+) -> std::io::Result<usize> {
+    // SAFETY: the iovecs point into buffers the caller keeps alive for the call
+    let n = unsafe {
+        match op {
+            IoOp::Read => libc::preadv2(
+                file.as_raw_fd(),
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_int,
+                offset as libc::off_t,
+                bits,
+            ),
+            IoOp::Write => libc::pwritev2(
+                file.as_raw_fd(),
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_int,
+                offset as libc::off_t,
+                bits,
+            ),
+        }
+    };
+    if n < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, os::unix::fs::FileExt, path::PathBuf};
+
+    use super::*;
+
+    struct TempFile {
+        path: PathBuf,
+        file: File,
+    }
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!(
+                "io-experiments-{name}-{pid}",
+                pid = std::process::id()
+            ));
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            TempFile { path, file }
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn iovecs(segments: &mut [Vec<u8>]) -> Vec<libc::iovec> {
+        segments
+            .iter_mut()
+            .map(|segment| libc::iovec {
+                iov_base: segment.as_mut_ptr().cast(),
+                iov_len: segment.len(),
+            })
+            .collect()
+    }
+
+    // Passes at most `limit` bytes of `iovecs` to the call, as a short transfer would.
+    fn capped(iovecs: &[libc::iovec], limit: usize) -> Vec<libc::iovec> {
+        let mut left = limit;
+        let mut capped = Vec::new();
+        for iovec in iovecs {
+            if left == 0 {
+                break;
+            }
+            let len = iovec.iov_len.min(left);
+            capped.push(libc::iovec {
+                iov_base: iovec.iov_base,
+                iov_len: len,
+            });
+            left -= len;
+        }
+        capped
+    }
+
+    #[test]
+    fn partial_transfers_advance_the_iovecs() {
+        let temp = TempFile::new("partial-transfers");
+        let mut written = (0..3)
+            .map(|segment| (0..4096).map(|i| (i * 7 + segment * 13) as u8).collect())
+            .collect::<Vec<Vec<u8>>>();
+        let calls = Cell::new(0);
+        // short calls that end in the middle of a segment and right at its end
+        transfer_with(
+            IoOp::Write,
+            &iovecs(&mut written),
+            512,
+            0,
+            |iovecs, offset, bits| {
+                calls.set(calls.get() + 1);
+                let limit = if calls.get() == 2 { 5192 } else { 3000 };
+                call(
+                    &temp.file,
+                    IoOp::Write,
+                    &capped(iovecs, limit),
+                    offset,
+                    bits,
+                )
+            },
+        );
+        // 3000, 5192 (up to the end of the second segment), 3000, 1096
+        assert_eq!(calls.get(), 4);
+        let mut file_data = vec![0; 512 + 3 * 4096];
+        temp.file.read_exact_at(&mut file_data, 0).unwrap();
+        assert_eq!(&file_data[..512], &[0; 512]);
+        assert_eq!(file_data[512..], written.concat());
+
+        let mut read = vec![vec![0u8; 1000], vec![0u8; 4096], vec![0u8; 7192]];
+        transfer_with(
+            IoOp::Read,
+            &iovecs(&mut read),
+            512,
+            0,
+            |iovecs, offset, bits| {
+                call(&temp.file, IoOp::Read, &capped(iovecs, 1500), offset, bits)
+            },
+        );
+        assert_eq!(read.concat(), written.concat());
+    }
+
+    #[test]
+    fn nowait_falls_back_to_blocking() {
+        let temp = TempFile::new("nowait-fallback");
+        let mut data = vec![vec![1u8; 4096]];
+        let bits_seen = std::cell::RefCell::new(Vec::new());
+        take_nowait_fallbacks();
+        transfer_with(
+            IoOp::Write,
+            &iovecs(&mut data),
+            0,
+            libc::RWF_NOWAIT | libc::RWF_DSYNC,
+            |iovecs, offset, bits| {
+                bits_seen.borrow_mut().push(bits);
+                if bits & libc::RWF_NOWAIT != 0 {
+                    // part of the data goes through before the call would block
+                    if offset == 0 {
+                        return call(&temp.file, IoOp::Write, &capped(iovecs, 1024), offset, 0);
+                    }
+                    return Err(std::io::Error::from_raw_os_error(libc::EAGAIN));
+                }
+                call(&temp.file, IoOp::Write, iovecs, offset, 0)
+            },
+        );
+        assert_eq!(
+            bits_seen.into_inner(),
+            [
+                libc::RWF_NOWAIT | libc::RWF_DSYNC,
+                libc::RWF_NOWAIT | libc::RWF_DSYNC,
+                libc::RWF_DSYNC,
+            ]
+        );
+        assert_eq!(take_nowait_fallbacks(), 1);
+        assert_eq!(std::fs::read(&temp.path).unwrap(), data.concat());
+    }
+
+    #[test]
+    #[should_panic(expected = "Read at 0: unexpected end of file")]
+    fn read_past_the_end() {
+        let temp = TempFile::new("read-past-the-end");
+        let mut buf = vec![vec![0u8; 4096]];
+        transfer(&temp.file, IoOp::Read, &iovecs(&mut buf), 0, &[]);
+    }
+}
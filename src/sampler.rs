@@ -0,0 +1,148 @@
+use std::{
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThroughputSample {
+    // end of the interval, from the start of the pass
+    pub t_secs: f64,
+    pub mbps: f64,
+}
+
+// Backends add completed bytes with a relaxed atomic add; a separate thread turns the counter into
+// throughput samples so the I/O path never waits on a lock.
+pub struct Sampler {
+    interval: Duration,
+    bytes: AtomicU64,
+    samples: Mutex<Vec<ThroughputSample>>,
+    stop: Mutex<bool>,
+    stopped: Condvar,
+}
+
+impl Sampler {
+    pub fn new(interval: Duration) -> Sampler {
+        Sampler {
+            interval,
+            bytes: AtomicU64::new(0),
+            samples: Mutex::new(Vec::new()),
+            stop: Mutex::new(false),
+            stopped: Condvar::new(),
+        }
+    }
+
+    pub fn record(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Samples taken so far by a running `sample_during`.
+    pub fn samples(&self) -> Vec<ThroughputSample> {
+        self.samples.lock().unwrap().clone()
+    }
+
+    // Samples throughput every interval while `pass` runs, plus a last, shorter interval at the end.
+    pub fn sample_during(&self, pass: impl FnOnce()) -> Vec<ThroughputSample> {
+        self.bytes.store(0, Ordering::Relaxed);
+        self.samples.lock().unwrap().clear();
+        *self.stop.lock().unwrap() = false;
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            let sampling = scope.spawn(|| {
+                let mut prev_bytes = 0;
+                let mut prev_time = start;
+                let mut stop = self.stop.lock().unwrap();
+                loop {
+                    let deadline = prev_time + self.interval;
+                    while !*stop && Instant::now() < deadline {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        stop = self.stopped.wait_timeout(stop, timeout).unwrap().0;
+                    }
+                    let now = Instant::now();
+                    let bytes = self.bytes.load(Ordering::Relaxed);
+                    let elapsed = now - prev_time;
+                    if !elapsed.is_zero() {
+                        self.samples.lock().unwrap().push(ThroughputSample {
+                            t_secs: (now - start).as_secs_f64(),
+                            mbps: (bytes - prev_bytes) as f64
+                                / 1024.0
+                                / 1024.0
+                                / elapsed.as_secs_f64(),
+                        });
+                    }
+                    if *stop {
+                        return;
+                    }
+                    prev_bytes = bytes;
+                    prev_time = now;
+                }
+            });
+            // stops the sampling thread even if the pass panics, the scope would wait for it
+            // forever otherwise
+            let stop = StopOnDrop(self);
+            pass();
+            drop(stop);
+            sampling.join().unwrap();
+        });
+        std::mem::take(&mut *self.samples.lock().unwrap())
+    }
+}
+
+struct StopOnDrop<'a>(&'a Sampler);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        *self
+            .0
+            .stop
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        self.0.stopped.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    use super::*;
+
+    #[test]
+    fn samples_a_pass() {
+        let sampler = Sampler::new(Duration::from_millis(10));
+        let samples = sampler.sample_during(|| {
+            for _ in 0..5 {
+                sampler.record(1024 * 1024);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+        assert!(samples.len() >= 2);
+        assert!(
+            samples
+                .windows(2)
+                .all(|pair| pair[0].t_secs < pair[1].t_secs)
+        );
+        let mib = samples
+            .iter()
+            .zip(std::iter::once(0.0).chain(samples.iter().map(|sample| sample.t_secs)))
+            .map(|(sample, prev_t_secs)| sample.mbps * (sample.t_secs - prev_t_secs))
+            .sum::<f64>();
+        assert!((mib - 5.0).abs() < 1e-6, "{mib} MiB sampled");
+    }
+
+    #[test]
+    fn panicking_pass_stops_sampling() {
+        let sampler = Sampler::new(Duration::from_millis(10));
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            sampler.sample_during(|| panic!("I/O failed"));
+        }));
+        assert!(result.is_err());
+        // usable again afterwards
+        let samples = sampler.sample_during(|| sampler.record(1));
+        assert_eq!(samples.len(), 1);
+    }
+}
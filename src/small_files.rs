@@ -0,0 +1,453 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs::OpenOptions,
+    os::{
+        fd::RawFd,
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileExt, OpenOptionsExt},
+        },
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use io_uring::{IoUring, opcode, squeue, types};
+use libc::O_DIRECT;
+use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    io_data::{aligned_vec, buf_data},
+    latency::LatencySummary,
+    run_benchmark::drop_caches,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmallFilesSettings {
+    #[serde(flatten)]
+    pub method: SmallFilesMethod,
+    pub file_count: u64,
+    pub min_file_size: u32,
+    pub max_file_size: u32,
+    pub fan_out: u32,
+    pub depth: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmallFilesMethod {
+    Buffered,
+    Direct,
+    BufferedUring { concurrency: u32 },
+    DirectUring { concurrency: u32 },
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmallFilesPhase {
+    Write,
+    Read,
+    Stat,
+    Unlink,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SmallFileOp {
+    Create,
+    Write,
+    Fsync,
+    Open,
+    Read,
+    Close,
+    Stat,
+    Unlink,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmallFilesReportItem {
+    #[serde(default)]
+    pub machine: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    pub settings: SmallFilesSettings,
+    pub phases: Vec<SmallFilesPhaseResult>,
+    pub ops: Vec<SmallFileOpResult>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmallFilesPhaseResult {
+    pub phase: SmallFilesPhase,
+    pub files_per_sec: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmallFileOpResult {
+    pub op: SmallFileOp,
+    pub latency: LatencySummary,
+}
+
+type Latencies = BTreeMap<SmallFileOp, Vec<Duration>>;
+
+impl SmallFilesSettings {
+    // Checked when the settings are read, so a bad workload fails before anything runs.
+    pub fn validate(&self) {
+        assert!(
+            self.min_file_size >= 4096,
+            "small files: min_file_size must be at least 4096"
+        );
+        assert!(
+            self.min_file_size <= self.max_file_size,
+            "small files: min_file_size must not exceed max_file_size"
+        );
+        assert!(self.fan_out > 0, "small files: fan_out must be at least 1");
+        if let SmallFilesMethod::BufferedUring { concurrency }
+        | SmallFilesMethod::DirectUring { concurrency } = self.method
+        {
+            assert!(
+                concurrency > 0,
+                "small files: concurrency must be at least 1"
+            );
+        }
+    }
+}
+
+pub fn run_small_files(dir: &Path, settings: &SmallFilesSettings) -> SmallFilesReportItem {
+    remove_dir_maybe(dir);
+    let paths = create_tree(dir, settings);
+    let mut rng = rng();
+    // multiples of 4096 so that the same sizes work with O_DIRECT
+    let sizes = (0..settings.file_count)
+        .map(|_| {
+            rng.random_range(settings.min_file_size / 4096..=settings.max_file_size / 4096) * 4096
+        })
+        .collect::<Vec<_>>();
+    let data = buf_data(settings.max_file_size as usize);
+    // set up before the phases so that they only time the file operations
+    let mut uring = match settings.method {
+        SmallFilesMethod::BufferedUring { concurrency }
+        | SmallFilesMethod::DirectUring { concurrency } => {
+            Some(UringState::new(&paths, data.len(), concurrency))
+        }
+        SmallFilesMethod::Buffered | SmallFilesMethod::Direct => None,
+    };
+    let mut latencies = Latencies::new();
+    let mut phases = Vec::new();
+    for phase in [
+        SmallFilesPhase::Write,
+        SmallFilesPhase::Read,
+        SmallFilesPhase::Stat,
+        SmallFilesPhase::Unlink,
+    ] {
+        drop_caches();
+        let start = Instant::now();
+        match settings.method {
+            SmallFilesMethod::Buffered => {
+                run_phase_sync(phase, &paths, &sizes, &data, 0, &mut latencies)
+            }
+            SmallFilesMethod::Direct => {
+                run_phase_sync(phase, &paths, &sizes, &data, O_DIRECT, &mut latencies)
+            }
+            SmallFilesMethod::BufferedUring { .. } => run_phase_uring(
+                phase,
+                uring.as_mut().unwrap(),
+                &sizes,
+                &data,
+                0,
+                &mut latencies,
+            ),
+            SmallFilesMethod::DirectUring { .. } => run_phase_uring(
+                phase,
+                uring.as_mut().unwrap(),
+                &sizes,
+                &data,
+                O_DIRECT,
+                &mut latencies,
+            ),
+        }
+        let elapsed = start.elapsed();
+        phases.push(SmallFilesPhaseResult {
+            phase,
+            files_per_sec: paths.len() as f64 / elapsed.as_secs_f64(),
+        });
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+
+    SmallFilesReportItem {
+        machine: None,
+        target: None,
+        settings: settings.clone(),
+        phases,
+        ops: latencies
+            .into_iter()
+            .map(|(op, mut samples)| SmallFileOpResult {
+                op,
+                latency: LatencySummary::from_samples(&mut samples),
+            })
+            .collect(),
+    }
+}
+
+fn create_tree(dir: &Path, settings: &SmallFilesSettings) -> Vec<PathBuf> {
+    let leaf_count = (settings.fan_out as u64).pow(settings.depth);
+    (0..settings.file_count)
+        .map(|file_idx| {
+            let mut leaf_idx = file_idx % leaf_count;
+            let mut path = dir.to_path_buf();
+            for _ in 0..settings.depth {
+                path.push(format!("d{}", leaf_idx % settings.fan_out as u64));
+                leaf_idx /= settings.fan_out as u64;
+            }
+            if file_idx < leaf_count {
+                std::fs::create_dir_all(&path).unwrap();
+            }
+            path.push(format!("f{file_idx}"));
+            path
+        })
+        .collect()
+}
+
+fn remove_dir_maybe(dir: &Path) {
+    match std::fs::remove_dir_all(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => panic!("error: {e}"),
+    }
+}
+
+fn timed<T>(latencies: &mut Latencies, op: SmallFileOp, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    latencies.entry(op).or_default().push(start.elapsed());
+    result
+}
+
+fn run_phase_sync(
+    phase: SmallFilesPhase,
+    paths: &[PathBuf],
+    sizes: &[u32],
+    data: &[u8],
+    flags: i32,
+    latencies: &mut Latencies,
+) {
+    let mut buf = aligned_vec(data.len());
+    for (path, &size) in paths.iter().zip(sizes) {
+        let size = size as usize;
+        match phase {
+            SmallFilesPhase::Write => {
+                let file = timed(latencies, SmallFileOp::Create, || {
+                    OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .custom_flags(flags)
+                        .open(path)
+                        .unwrap()
+                });
+                timed(latencies, SmallFileOp::Write, || {
+                    file.write_all_at(&data[..size], 0).unwrap()
+                });
+                timed(latencies, SmallFileOp::Fsync, || file.sync_all().unwrap());
+                timed(latencies, SmallFileOp::Close, || drop(file));
+            }
+            SmallFilesPhase::Read => {
+                let file = timed(latencies, SmallFileOp::Open, || {
+                    OpenOptions::new()
+                        .read(true)
+                        .custom_flags(flags)
+                        .open(path)
+                        .unwrap()
+                });
+                timed(latencies, SmallFileOp::Read, || {
+                    file.read_exact_at(&mut buf[..size], 0).unwrap()
+                });
+                timed(latencies, SmallFileOp::Close, || drop(file));
+            }
+            SmallFilesPhase::Stat => {
+                let metadata = timed(latencies, SmallFileOp::Stat, || {
+                    std::fs::metadata(path).unwrap()
+                });
+                assert_eq!(metadata.len(), size as u64);
+            }
+            SmallFilesPhase::Unlink => {
+                timed(latencies, SmallFileOp::Unlink, || {
+                    std::fs::remove_file(path).unwrap()
+                });
+            }
+        }
+    }
+}
+
+fn phase_ops(phase: SmallFilesPhase) -> &'static [SmallFileOp] {
+    match phase {
+        SmallFilesPhase::Write => &[
+            SmallFileOp::Create,
+            SmallFileOp::Write,
+            SmallFileOp::Fsync,
+            SmallFileOp::Close,
+        ],
+        SmallFilesPhase::Read => &[SmallFileOp::Open, SmallFileOp::Read, SmallFileOp::Close],
+        SmallFilesPhase::Stat => &[SmallFileOp::Stat],
+        SmallFilesPhase::Unlink => &[SmallFileOp::Unlink],
+    }
+}
+
+struct UringSlot {
+    file_idx: usize,
+    step: usize,
+    fd: RawFd,
+    op_start: Instant,
+    buf: Vec<u8>,
+    statx: Box<libc::statx>,
+}
+
+// The ring and the per-slot buffers, shared by the phases of a run.
+struct UringState {
+    uring: IoUring,
+    paths: Vec<CString>,
+    slots: Vec<UringSlot>,
+}
+
+impl UringState {
+    fn new(paths: &[PathBuf], buf_size: usize, concurrency: u32) -> UringState {
+        UringState {
+            uring: IoUring::new(concurrency.next_power_of_two()).unwrap(),
+            paths: paths
+                .iter()
+                .map(|path| CString::new(path.as_os_str().as_bytes()).unwrap())
+                .collect(),
+            slots: (0..concurrency)
+                .map(|_| UringSlot {
+                    file_idx: 0,
+                    step: 0,
+                    fd: -1,
+                    op_start: Instant::now(),
+                    buf: aligned_vec(buf_size),
+                    // SAFETY: statx is a plain C struct, all zeroes is a valid value
+                    statx: Box::new(unsafe { std::mem::zeroed() }),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn run_phase_uring(
+    phase: SmallFilesPhase,
+    state: &mut UringState,
+    sizes: &[u32],
+    data: &[u8],
+    flags: i32,
+    latencies: &mut Latencies,
+) {
+    let ops = phase_ops(phase);
+    let UringState {
+        uring,
+        paths,
+        slots,
+    } = state;
+    let mut next_file = 0;
+    let mut files_in_flight = 0;
+    for (slot_idx, slot) in slots.iter_mut().enumerate() {
+        if next_file == paths.len() {
+            break;
+        }
+        slot.file_idx = next_file;
+        slot.step = 0;
+        next_file += 1;
+        files_in_flight += 1;
+        let entry = uring_entry(ops[0], slot, paths, sizes, data, flags);
+        push_entry(uring, slot, entry.user_data(slot_idx as u64));
+    }
+    while files_in_flight > 0 {
+        uring.submit_and_wait(1).unwrap();
+        let completed = uring
+            .completion()
+            .map(|entry| (entry.user_data() as usize, entry.result()))
+            .collect::<Vec<_>>();
+        for (slot_idx, result) in completed {
+            let slot = &mut slots[slot_idx];
+            let op = ops[slot.step];
+            latencies
+                .entry(op)
+                .or_default()
+                .push(slot.op_start.elapsed());
+            let size = sizes[slot.file_idx] as i32;
+            match op {
+                SmallFileOp::Create | SmallFileOp::Open => {
+                    assert!(
+                        result >= 0,
+                        "openat: {}",
+                        std::io::Error::from_raw_os_error(-result)
+                    );
+                    slot.fd = result;
+                }
+                SmallFileOp::Write | SmallFileOp::Read => assert_eq!(result, size),
+                SmallFileOp::Stat => {
+                    assert_eq!(result, 0);
+                    assert_eq!(slot.statx.stx_size, size as u64);
+                }
+                SmallFileOp::Fsync | SmallFileOp::Close | SmallFileOp::Unlink => {
+                    assert_eq!(result, 0)
+                }
+            }
+            slot.step += 1;
+            if slot.step == ops.len() {
+                files_in_flight -= 1;
+                if next_file == paths.len() {
+                    continue;
+                }
+                slot.file_idx = next_file;
+                slot.step = 0;
+                next_file += 1;
+                files_in_flight += 1;
+            }
+            let entry = uring_entry(ops[slot.step], slot, paths, sizes, data, flags);
+            push_entry(uring, slot, entry.user_data(slot_idx as u64));
+        }
+    }
+}
+
+fn uring_entry(
+    op: SmallFileOp,
+    slot: &mut UringSlot,
+    paths: &[CString],
+    sizes: &[u32],
+    data: &[u8],
+    flags: i32,
+) -> squeue::Entry {
+    let path = paths[slot.file_idx].as_ptr();
+    let size = sizes[slot.file_idx];
+    let cwd = types::Fd(libc::AT_FDCWD);
+    match op {
+        SmallFileOp::Create => opcode::OpenAt::new(cwd, path)
+            .flags(libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY | libc::O_CLOEXEC | flags)
+            .mode(0o644)
+            .build(),
+        SmallFileOp::Open => opcode::OpenAt::new(cwd, path)
+            .flags(libc::O_RDONLY | libc::O_CLOEXEC | flags)
+            .build(),
+        SmallFileOp::Write => opcode::Write::new(types::Fd(slot.fd), data.as_ptr(), size).build(),
+        SmallFileOp::Fsync => opcode::Fsync::new(types::Fd(slot.fd)).build(),
+        SmallFileOp::Read => {
+            opcode::Read::new(types::Fd(slot.fd), slot.buf.as_mut_ptr(), size).build()
+        }
+        SmallFileOp::Close => opcode::Close::new(types::Fd(slot.fd)).build(),
+        SmallFileOp::Stat => opcode::Statx::new(
+            cwd,
+            path,
+            &mut *slot.statx as *mut libc::statx as *mut types::statx,
+        )
+        .mask(libc::STATX_BASIC_STATS)
+        .build(),
+        SmallFileOp::Unlink => opcode::UnlinkAt::new(cwd, path).build(),
+    }
+}
+
+fn push_entry(uring: &mut IoUring, slot: &mut UringSlot, entry: squeue::Entry) {
+    slot.op_start = Instant::now();
+    unsafe {
+        // SAFETY: paths, buffers and statx outlive the ring operations
+        uring.submission().push(&entry).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    IoMethod, IoOp,
+    bench_settings::IoMethodSettings,
+    io_data::Workload,
+    sampler::{Sampler, ThroughputSample},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SteadyStateSettings {
+    #[serde(default = "default_ops")]
+    pub ops: Vec<IoOp>,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: f64,
+    // allowed deviation from the window average, as a fraction
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    #[serde(default = "default_max_duration_secs")]
+    pub max_duration_secs: f64,
+    #[serde(default = "default_sample_interval_ms")]
+    pub sample_interval_ms: u64,
+}
+
+fn default_ops() -> Vec<IoOp> {
+    vec![IoOp::Write]
+}
+
+fn default_window_secs() -> f64 {
+    30.0
+}
+
+fn default_tolerance() -> f64 {
+    0.1
+}
+
+fn default_max_duration_secs() -> f64 {
+    600.0
+}
+
+fn default_sample_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SteadyStateResult {
+    pub op: IoOp,
+    // average over the first window
+    pub burst_mbps: f64,
+    // average over the last window, steady or not
+    pub steady_mbps: f64,
+    pub reached: bool,
+    pub duration_secs: f64,
+    pub samples: Vec<ThroughputSample>,
+}
+
+// Repeats full passes over the file, which must already be written, until the throughput over
+// the last window is stable or max_duration_secs is reached. Both are only checked between passes,
+// so a run can overshoot by up to one pass.
+pub fn measure_steady_state(
+    workload: &Workload,
+    io_method: &IoMethodSettings,
+    settings: &SteadyStateSettings,
+) -> Vec<SteadyStateResult> {
+    let mut results = Vec::new();
+    for &op in &settings.ops {
+        let sampler = Sampler::new(Duration::from_millis(settings.sample_interval_ms));
+        let sampled = Workload {
+            sampler: Some(&sampler),
+            ..*workload
+        };
+        let max_duration = Duration::from_secs_f64(settings.max_duration_secs);
+        let start = Instant::now();
+        let mut reached = false;
+        let samples = sampler.sample_during(|| {
+            loop {
+                match op {
+                    IoOp::Read => io_method.read_file(&sampled),
+                    IoOp::Write => io_method.write_file(&sampled),
+                }
+                if is_steady(&sampler.samples(), settings) {
+                    reached = true;
+                    break;
+                }
+                if start.elapsed() >= max_duration {
+                    break;
+                }
+            }
+        });
+        let duration_secs = start.elapsed().as_secs_f64();
+        let last_t = samples.last().map_or(0.0, |sample| sample.t_secs);
+        let result = SteadyStateResult {
+            op,
+            burst_mbps: mean_mbps(
+                samples
+                    .iter()
+                    .filter(|sample| sample.t_secs <= settings.window_secs),
+            ),
+            steady_mbps: mean_mbps(window(&samples, last_t, settings.window_secs)),
+            reached,
+            duration_secs,
+            samples,
+        };
+        println!(
+            "steady state {op:?} {io_method:?} {sequence:?} => burst {burst:.2} MiB/sec, {state} {steady:.2} MiB/sec after {duration_secs:.1} sec",
+            sequence = workload.sequence,
+            burst = result.burst_mbps,
+            state = if reached { "steady" } else { "not steady," },
+            steady = result.steady_mbps,
+        );
+        results.push(result);
+    }
+    results
+}
+
+fn window(
+    samples: &[ThroughputSample],
+    last_t: f64,
+    window_secs: f64,
+) -> impl Iterator<Item = &ThroughputSample> {
+    samples
+        .iter()
+        .filter(move |sample| sample.t_secs > last_t - window_secs)
+}
+
+fn mean_mbps<'a>(samples: impl Iterator<Item = &'a ThroughputSample>) -> f64 {
+    let (count, sum) = samples.fold((0, 0.0), |(count, sum), sample| {
+        (count + 1, sum + sample.mbps)
+    });
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
+
+// Similar to the SNIA PTS criteria: within the window, the range of the samples must stay within
+// ±tolerance of their average, and the least squares trend over the window within tolerance.
+fn is_steady(samples: &[ThroughputSample], settings: &SteadyStateSettings) -> bool {
+    let Some(last) = samples.last() else {
+        return false;
+    };
+    if last.t_secs < settings.window_secs {
+        return false;
+    }
+    let window = window(samples, last.t_secs, settings.window_secs).collect::<Vec<_>>();
+    if window.len() < 3 {
+        return false;
+    }
+    let n = window.len() as f64;
+    let mean_t = window.iter().map(|sample| sample.t_secs).sum::<f64>() / n;
+    let mean = window.iter().map(|sample| sample.mbps).sum::<f64>() / n;
+    if mean <= 0.0 {
+        return false;
+    }
+    let min = window
+        .iter()
+        .map(|sample| sample.mbps)
+        .fold(f64::MAX, f64::min);
+    let max = window.iter().map(|sample| sample.mbps).fold(0.0, f64::max);
+    let covariance = window
+        .iter()
+        .map(|sample| (sample.t_secs - mean_t) * (sample.mbps - mean))
+        .sum::<f64>();
+    let variance_t = window
+        .iter()
+        .map(|sample| (sample.t_secs - mean_t).powi(2))
+        .sum::<f64>();
+    let slope = covariance / variance_t;
+    max - min <= 2.0 * settings.tolerance * mean
+        && (slope * settings.window_secs).abs() <= settings.tolerance * mean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(window_secs: f64, tolerance: f64) -> SteadyStateSettings {
+        SteadyStateSettings {
+            ops: default_ops(),
+            window_secs,
+            tolerance,
+            max_duration_secs: default_max_duration_secs(),
+            sample_interval_ms: 1000,
+        }
+    }
+
+    // One sample per second, the first at t = 1.
+    fn series(mbps: impl IntoIterator<Item = f64>) -> Vec<ThroughputSample> {
+        mbps.into_iter()
+            .enumerate()
+            .map(|(i, mbps)| ThroughputSample {
+                t_secs: (i + 1) as f64,
+                mbps,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flat_series_is_steady() {
+        let samples = series([
+            100.0, 104.0, 97.0, 101.0, 99.0, 103.0, 96.0, 100.0, 102.0, 98.0,
+        ]);
+        assert!(is_steady(&samples, &settings(10.0, 0.1)));
+        // the same range is too wide for a 2% tolerance
+        assert!(!is_steady(&samples, &settings(10.0, 0.02)));
+    }
+
+    #[test]
+    fn ramping_series_is_not_steady() {
+        let samples = series((0..30).map(|i| 100.0 + 10.0 * i as f64));
+        assert!(!is_steady(&samples, &settings(10.0, 0.1)));
+        // within the range but still trending: 92 to 108 spans 16% of the mean where 20% would
+        // be allowed, the trend over the window 16% where 10% is
+        let samples = series((0..10).map(|i| 92.0 + 16.0 * i as f64 / 9.0));
+        assert!(!is_steady(&samples, &settings(10.0, 0.1)));
+    }
+
+    #[test]
+    fn only_the_last_window_counts() {
+        // a write burst into the cache, then the device rate
+        let samples = series(
+            [900.0, 850.0, 600.0, 300.0]
+                .into_iter()
+                .chain(std::iter::repeat_n(200.0, 10)),
+        );
+        assert!(is_steady(&samples, &settings(10.0, 0.05)));
+        assert!(!is_steady(&samples, &settings(12.0, 0.05)));
+        assert_eq!(
+            window(&samples, 14.0, 10.0)
+                .map(|sample| sample.t_secs)
+                .collect::<Vec<_>>(),
+            (5..=14).map(|t| t as f64).collect::<Vec<_>>()
+        );
+        assert_eq!(mean_mbps(window(&samples, 14.0, 10.0)), 200.0);
+        assert_eq!(
+            mean_mbps(window(&samples, 14.0, 12.0)),
+            (600.0 + 300.0 + 2000.0) / 12.0
+        );
+    }
+
+    #[test]
+    fn shorter_than_the_window_is_not_steady() {
+        let samples = series(std::iter::repeat_n(100.0, 9));
+        assert!(!is_steady(&samples, &settings(10.0, 0.1)));
+        assert!(is_steady(&samples, &settings(9.0, 0.1)));
+        // too few samples for a trend
+        assert!(!is_steady(&series([100.0, 100.0]), &settings(1.0, 0.1)));
+        assert!(!is_steady(&[], &settings(10.0, 0.1)));
+        assert!(!is_steady(
+            &series(std::iter::repeat_n(0.0, 10)),
+            &settings(10.0, 0.1)
+        ));
+        assert_eq!(mean_mbps([].iter()), 0.0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use minijinja::{Environment, Error, Value};
+
+use crate::charts;
+
+pub const REPORT_HTML: &str = include_str!("../report.jinja.html");
+pub const COMPARE_HTML: &str = include_str!("../compare.jinja.html");
+pub const COMPARE_MARKDOWN: &str = include_str!("../compare.jinja.md");
+pub const INDEX_HTML: &str = include_str!("../index.jinja.html");
+
+// Environment with the helpers available to every template, built-in or custom, and `template`
+// loaded as "main": the file at `path` if given, `default` otherwise.
+pub fn environment(path: Option<&Path>, default: &'static str) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_function("sparkline", charts::sparkline);
+    env.add_filter("mbps", mbps);
+    env.add_filter("bytes", bytes);
+    env.add_filter("secs", secs);
+    env.add_filter("pct_delta", pct_delta);
+    env.add_filter("distinct", distinct);
+    env.add_filter("max_by", max_by);
+    match path {
+        Some(path) => env
+            .add_template_owned("main", std::fs::read_to_string(path).unwrap())
+            .unwrap(),
+        None => env.add_template("main", default).unwrap(),
+    }
+    env
+}
+
+// Throughput given in MiB/s, e.g. `1.25 GiB/s`.
+fn mbps(value: f64) -> String {
+    if value >= 1024.0 {
+        format!("{:.2} GiB/s", value / 1024.0)
+    } else if value >= 1.0 {
+        format!("{value:.1} MiB/s")
+    } else {
+        format!("{:.1} KiB/s", value * 1024.0)
+    }
+}
+
+// Sizes in bytes, e.g. `4 KiB`; whole numbers drop the decimals.
+fn bytes(value: f64) -> String {
+    let mut value = value;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return if value.fract() == 0.0 {
+                format!("{value} {unit}")
+            } else {
+                format!("{value:.1} {unit}")
+            };
+        }
+        value /= 1024.0;
+    }
+    format!("{value:.1} TiB")
+}
+
+fn secs(value: f64) -> String {
+    if value >= 1.0 {
+        format!("{value:.2} s")
+    } else if value >= 1e-3 {
+        format!("{:.2} ms", value * 1e3)
+    } else {
+        format!("{:.1} µs", value * 1e6)
+    }
+}
+
+// `value | pct_delta(baseline)`, e.g. `+12.5%`; empty if there's no baseline to compare with.
+fn pct_delta(value: f64, baseline: Option<f64>) -> String {
+    match baseline {
+        Some(baseline) if baseline != 0.0 => {
+            format!("{:+.1}%", (value - baseline) / baseline * 100.0)
+        }
+        _ => String::new(),
+    }
+}
+
+// Sorted distinct values of a dotted attribute path, e.g. `report_items | distinct("method.type")`,
+// for building pivot headers.
+fn distinct(items: Vec<Value>, path: &str) -> Result<Vec<Value>, Error> {
+    let mut values = items
+        .iter()
+        .map(|item| attr_path(item, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    values.sort();
+    values.dedup();
+    Ok(values)
+}
+
+// The item with the largest value at the attribute path, e.g. the fastest row of a group.
+fn max_by(items: Vec<Value>, path: &str) -> Result<Value, Error> {
+    let mut best = None;
+    for item in items {
+        let value = attr_path(&item, path)?;
+        if best
+            .as_ref()
+            .is_none_or(|(best_value, _)| value > *best_value)
+        {
+            best = Some((value, item));
+        }
+    }
+    Ok(best.map_or(Value::UNDEFINED, |(_, item)| item))
+}
+
+fn attr_path(item: &Value, path: &str) -> Result<Value, Error> {
+    path.split('.')
+        .try_fold(item.clone(), |value, attr| value.get_attr(attr))
+}